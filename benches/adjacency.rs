@@ -0,0 +1,126 @@
+//! Compares looking up valid neighbors through the `HashMap` of `Node`s against the dense `Adjacency` table.
+//! Run with `cargo bench --bench adjacency`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bitvec::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wfc_voxel::{Direction, NodeSet};
+
+const NODE_SIZE: usize = 4;
+const ASSETS: usize = 32;
+const SAMPLES: usize = 1000;
+const ROUNDS: usize = 20;
+
+static DIRECTIONS: [Direction; 6] = [
+    Direction::POSX,
+    Direction::NEGX,
+    Direction::POSY,
+    Direction::NEGY,
+    Direction::POSZ,
+    Direction::NEGZ,
+];
+
+/// Writes `ASSETS` random `XRAW` files, each with a solid floor so the set stays solvable.
+fn write_assets(dir: &Path, rng: &mut StdRng) {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+
+    for asset in 0..ASSETS {
+        let mut buffer = b"XRAW".to_vec();
+        buffer.extend([0, 1, 8, 8]);
+        for _ in 0..3 {
+            buffer.extend((NODE_SIZE as u32).to_le_bytes());
+        }
+        buffer.extend(256u32.to_le_bytes());
+
+        for z in 0..NODE_SIZE {
+            for _ in 0..NODE_SIZE * NODE_SIZE {
+                let voxel = if z == 0 || rng.gen_bool(0.2) { rng.gen_range(1..3) } else { 0 };
+                buffer.push(voxel);
+            }
+        }
+        buffer.extend([0; 256 * 4]);
+
+        fs::write(dir.join(format!("asset_{}.xraw", asset)), buffer).unwrap();
+    }
+}
+
+fn node_dict_union(node_set: &NodeSet, node_ids: &BitVec, dir: &Direction) -> BitVec {
+    let mut ret = BitVec::repeat(false, node_set.node_dict().len());
+
+    for id in node_ids.iter_ones() {
+        let node = &node_set.node_dict()[&id];
+
+        match dir {
+            Direction::POSX => { ret |= &node.valid_neighbors.px; }
+            Direction::NEGX => { ret |= &node.valid_neighbors.nx; }
+            Direction::POSY => { ret |= &node.valid_neighbors.py; }
+            Direction::NEGY => { ret |= &node.valid_neighbors.ny; }
+            Direction::POSZ => { ret |= &node.valid_neighbors.pz; }
+            Direction::NEGZ => { ret |= &node.valid_neighbors.nz; }
+        }
+    }
+
+    ret
+}
+
+fn adjacency_union(node_set: &NodeSet, node_ids: &BitVec, dir: &Direction) -> BitVec {
+    let adjacency = node_set.adjacency();
+    let mut ret = BitVec::repeat(false, adjacency.len());
+    adjacency.union_into(node_ids, dir, &mut ret);
+
+    ret
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS as u32
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let dir = std::env::temp_dir().join("wfc_voxel_bench_assets");
+    write_assets(&dir, &mut rng);
+
+    let node_set = NodeSet::new(NODE_SIZE, &dir, HashSet::new()).unwrap();
+    let len = node_set.node_dict().len();
+
+    let samples: Vec<BitVec> = (0..SAMPLES)
+        .map(|_| (0..len).map(|_| rng.gen_bool(0.5)).collect())
+        .collect();
+
+    for sample in &samples {
+        for dir in &DIRECTIONS {
+            assert_eq!(node_dict_union(&node_set, sample, dir), adjacency_union(&node_set, sample, dir));
+        }
+    }
+
+    let node_dict_time = time(|| {
+        for sample in &samples {
+            for dir in &DIRECTIONS {
+                black_box(node_dict_union(&node_set, black_box(sample), dir));
+            }
+        }
+    });
+    let adjacency_time = time(|| {
+        for sample in &samples {
+            for dir in &DIRECTIONS {
+                black_box(adjacency_union(&node_set, black_box(sample), dir));
+            }
+        }
+    });
+
+    println!("{} nodes, {} unions per round", len, SAMPLES * DIRECTIONS.len());
+    println!("node dict:       {:?} per round", node_dict_time);
+    println!("adjacency table: {:?} per round", adjacency_time);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use bitvec::prelude::*;
+
+use super::node::Node;
+use super::Direction;
+
+/// The valid neighbors of every `Node` in every direction, stored in one contiguous array of words.
+/// Rows are indexed by node id and direction, and padded to whole words so they can be combined a word at a time.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Adjacency {
+    len: usize,
+    stride: usize,
+    words: Vec<usize>,
+    joined: Vec<BitVec>,
+}
+
+impl Adjacency {
+
+    /// Builds the table from the valid neighbors of each `Node`.
+    pub fn from_node_dict(node_dict: &HashMap<usize, Node>) -> Self {
+        let len = node_dict.len();
+        let stride = len.div_ceil(usize::BITS as usize);
+        let mut words = vec![0; stride * len * 6];
+        let mut joined = vec![BitVec::repeat(false, len); 6];
+
+        for (id, node) in node_dict {
+            let rows = [
+                &node.valid_neighbors.px,
+                &node.valid_neighbors.nx,
+                &node.valid_neighbors.py,
+                &node.valid_neighbors.ny,
+                &node.valid_neighbors.pz,
+                &node.valid_neighbors.nz,
+            ];
+
+            for (dir, neighbors) in rows.into_iter().enumerate() {
+                let start = (id * 6 + dir) * stride;
+                words[start..start + stride].view_bits_mut::<Lsb0>()[..len].copy_from_bitslice(neighbors);
+            }
+
+            if let Some(part) = &node.part {
+                for (dir, joined) in joined.iter_mut().enumerate() {
+                    joined.set(*id, part.joined[dir]);
+                }
+            }
+        }
+
+        Self { len, stride, words, joined }
+    }
+
+    /// The number of `Node`s in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the table has no `Node`s.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The `Node`s that can be placed next to `node_id` in direction `dir`.
+    #[inline]
+    pub fn neighbors(&self, node_id: usize, dir: &Direction) -> &BitSlice {
+        &self.row(node_id, dir).view_bits::<Lsb0>()[..self.len]
+    }
+
+    /// The `Node`s joined to another part of their asset in direction `dir`, which can't sit on that edge of the map.
+    #[inline]
+    pub fn joined(&self, dir: &Direction) -> &BitSlice {
+        &self.joined[dir_index(dir)]
+    }
+
+    /// Adds the `Node`s that can be placed next to any of `node_ids` in direction `dir` to `neighbors`.
+    /// `neighbors` must be as long as the table.
+    #[inline]
+    pub fn union_into(&self, node_ids: &BitSlice, dir: &Direction, neighbors: &mut BitVec) {
+        debug_assert_eq!(neighbors.len(), self.len);
+
+        let neighbors = neighbors.as_raw_mut_slice();
+        for id in node_ids.iter_ones() {
+            for (word, row_word) in neighbors.iter_mut().zip(self.row(id, dir)) {
+                *word |= row_word;
+            }
+        }
+    }
+
+    #[inline]
+    fn row(&self, node_id: usize, dir: &Direction) -> &[usize] {
+        let start = (node_id * 6 + dir_index(dir)) * self.stride;
+        &self.words[start..start + self.stride]
+    }
+}
+
+/// The index of a direction in `DIRECTIONS`, which also orders the rows of the table.
+#[inline]
+pub(crate) fn dir_index(dir: &Direction) -> usize {
+    match dir {
+        Direction::POSX => 0,
+        Direction::NEGX => 1,
+        Direction::POSY => 2,
+        Direction::NEGY => 3,
+        Direction::POSZ => 4,
+        Direction::NEGZ => 5,
+    }
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use super::Rotations;
+
+/// Errors that can occur while building a `NodeSet` from voxel files.
+/// Every variant carries the path of the file or directory that failed.
+#[derive(Debug)]
+pub enum WfcError {
+    /// The file or directory could not be opened or read.
+    Io { path: PathBuf, source: io::Error },
+    /// The file does not start with the magic bytes of its format.
+    BadMagic { path: PathBuf, expected: &'static str },
+    /// The file stores palette indices with a bit depth other than 8.
+    UnsupportedBitDepth { path: PathBuf, bits: u8 },
+    /// The voxel array does not cover a whole number of cells of the node shape. Shapes are given as `[width, height, depth]`.
+    ShapeMismatch { path: PathBuf, expected: [usize; 3], found: [usize; 3] },
+    /// The file ended before all of its declared data could be read.
+    TruncatedData { path: PathBuf },
+    /// The file declares a model that is empty, or larger than its format allows. Sizes are given as `[x, y, z]` in the file's own axes.
+    InvalidSize { path: PathBuf, size: [usize; 3] },
+    /// The file holds no voxel models.
+    NoModels { path: PathBuf },
+    /// The file name could not be turned into an asset name.
+    BadFilename { path: PathBuf },
+    /// The asset has the same name as another asset, e.g. a model of a `.vox` file named after a file like `tile_0.xraw`.
+    DuplicateAsset { path: PathBuf, name: String },
+    /// A rule file could not be parsed or describes an inconsistent rule set. The path is empty for in-memory rules.
+    InvalidRules { path: PathBuf, reason: String },
+    /// The asset asks for rotations that would change its shape, like quarter turns of a node that isn't as wide as it is deep.
+    UnsupportedRotations { path: PathBuf, rotations: Rotations, shape: [usize; 3] },
+}
+
+impl WfcError {
+    /// The path of the file or directory that caused the error.
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            WfcError::Io { path, .. } => path,
+            WfcError::BadMagic { path, .. } => path,
+            WfcError::UnsupportedBitDepth { path, .. } => path,
+            WfcError::ShapeMismatch { path, .. } => path,
+            WfcError::TruncatedData { path } => path,
+            WfcError::InvalidSize { path, .. } => path,
+            WfcError::NoModels { path } => path,
+            WfcError::BadFilename { path } => path,
+            WfcError::DuplicateAsset { path, .. } => path,
+            WfcError::InvalidRules { path, .. } => path,
+            WfcError::UnsupportedRotations { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WfcError::Io { path, source } => {
+                write!(f, "could not read {}: {}", path.display(), source)
+            }
+            WfcError::BadMagic { path, expected } => {
+                write!(f, "{} is not a valid {} file", path.display(), expected)
+            }
+            WfcError::UnsupportedBitDepth { path, bits } => {
+                write!(f, "{} uses {} bits per index, only 8 is supported", path.display(), bits)
+            }
+            WfcError::ShapeMismatch { path, expected, found } => {
+                write!(f, "{} has shape {:?}, expected a multiple of {:?}", path.display(), found, expected)
+            }
+            WfcError::TruncatedData { path } => {
+                write!(f, "{} ended unexpectedly", path.display())
+            }
+            WfcError::InvalidSize { path, size } => {
+                write!(f, "{} declares a model of size {:?}, which is empty or too large", path.display(), size)
+            }
+            WfcError::NoModels { path } => {
+                write!(f, "{} does not hold any models", path.display())
+            }
+            WfcError::BadFilename { path } => {
+                write!(f, "{} does not have a valid asset name", path.display())
+            }
+            WfcError::DuplicateAsset { path, name } => {
+                write!(f, "{} holds an asset named {}, which is already taken", path.display(), name)
+            }
+            WfcError::InvalidRules { path, reason } if path.as_os_str().is_empty() => {
+                write!(f, "invalid rules: {}", reason)
+            }
+            WfcError::InvalidRules { path, reason } => {
+                write!(f, "invalid rules in {}: {}", path.display(), reason)
+            }
+            WfcError::UnsupportedRotations { path, rotations, shape } => {
+                write!(f, "{} cannot use {:?} rotations with node shape {:?}", path.display(), rotations, shape)
+            }
+        }
+    }
+}
+
+impl Error for WfcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WfcError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
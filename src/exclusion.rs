@@ -0,0 +1,138 @@
+use super::node::Node;
+use super::wfc::DIRECTIONS;
+use super::Direction;
+
+/// A rule keeping `Node`s of one asset from being placed next to `Node`s of another.
+/// Directions are given in the map, from `asset` towards `other`, and the rule always holds from both sides.
+/// `(asset, other)` tuples convert to a rule covering every direction and rotation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exclusion {
+    asset: String,
+    other: String,
+    directions: [bool; 6],
+    symmetric: bool,
+    rotation: Option<u8>,
+    other_rotation: Option<u8>,
+}
+
+impl Exclusion {
+
+    /// Keeps `other` from being placed next to `asset` in any direction.
+    pub fn new(asset: &str, other: &str) -> Self {
+        Self {
+            asset: asset.to_string(),
+            other: other.to_string(),
+            directions: [true; 6],
+            symmetric: false,
+            rotation: None,
+            other_rotation: None,
+        }
+    }
+
+    /// Limits the rule to the given directions, e.g. `POSY` alone keeps `other` off the top of `asset`.
+    pub fn directions(mut self, directions: &[Direction]) -> Self {
+        self.directions = std::array::from_fn(|dir| directions.contains(&DIRECTIONS[dir]));
+        self
+    }
+
+    /// Also keeps `asset` from being placed next to `other` in the same directions, so the order of the assets doesn't matter.
+    pub fn symmetric(mut self) -> Self {
+        self.symmetric = true;
+        self
+    }
+
+    /// Limits the rule to `Node`s of `asset` with the given number of quarter turns about the `Y` axis, mirrored or not.
+    /// `Node`s standing in for turns skipped as duplicates are included, while those tipped over by `Rotations::Cube` are not.
+    pub fn rotation(mut self, rotation: u8) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Limits the rule to `Node`s of `other` with the given rotation, counted like in `rotation`.
+    pub fn other_rotation(mut self, rotation: u8) -> Self {
+        self.other_rotation = Some(rotation);
+        self
+    }
+
+    /// Whether the rule keeps `other` from being placed next to `node` in direction `dir`, indexed like `DIRECTIONS`.
+    #[inline]
+    pub(crate) fn excludes(&self, node: &Node, other: &Node, dir: usize) -> bool {
+        // A rule seen from the other `Node` points the opposite way
+        self.matches(node, other, dir)
+            || self.matches(other, node, dir ^ 1)
+            || (self.symmetric && (self.matches(other, node, dir) || self.matches(node, other, dir ^ 1)))
+    }
+
+    #[inline]
+    fn matches(&self, node: &Node, other: &Node, dir: usize) -> bool {
+        self.directions[dir]
+            && node.asset_name == self.asset
+            && other.asset_name == self.other
+            && self.rotation.is_none_or(|rotation| has_rotation(node, rotation))
+            && self.other_rotation.is_none_or(|rotation| has_rotation(other, rotation))
+    }
+}
+
+/// Whether `node` stands upright with `rotation` quarter turns in any of its poses.
+#[inline]
+fn has_rotation(node: &Node, rotation: u8) -> bool {
+    node.poses().any(|(_, orientation)| orientation.up == Direction::POSY && orientation.turns == rotation)
+}
+
+impl From<(&str, &str)> for Exclusion {
+    fn from((asset, other): (&str, &str)) -> Self {
+        Self::new(asset, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{s, Array3};
+
+    use super::*;
+    use crate::{NodeSet, NodeSetBuilder};
+
+    /// Whether any `Node` of `other` may be placed next to the `Node` in any direction.
+    fn touches(node_set: &NodeSet, id: usize, other: &str) -> bool {
+        let other_bits = node_set.asset_bits(&other.to_string()).unwrap();
+        DIRECTIONS.iter().any(|dir| {
+            let neighbors = node_set.node_dict()[&id].valid_neighbors.get(dir);
+            other_bits.iter_ones().any(|other_id| neighbors[other_id])
+        })
+    }
+
+    #[test]
+    fn rotation_matches_turns_skipped_as_duplicates() {
+        // A wall across the `Z` axis looks the same after a half turn
+        let mut wall = Array3::<u8>::zeros((3, 3, 3));
+        wall.slice_mut(s![.., .., 1]).fill(1);
+
+        let node_set = NodeSetBuilder::new(3)
+            .asset("wall", wall)
+            .asset("air", Array3::zeros((3, 3, 3)))
+            .exclude(Exclusion::new("wall", "air").rotation(2))
+            .build()
+            .unwrap();
+
+        let wall_bits = node_set.asset_bits(&"wall".to_string()).unwrap();
+        assert_eq!(wall_bits.count_ones(), 2);
+        for id in wall_bits.iter_ones() {
+            let turned = node_set.node_dict()[&id].poses().any(|(_, orientation)| orientation.turns == 2);
+            assert_eq!(touches(&node_set, id, "air"), !turned);
+        }
+    }
+
+    #[test]
+    fn other_rotation_matches_symmetric_assets() {
+        let node_set = NodeSetBuilder::new(3)
+            .asset("stone", Array3::from_elem((3, 3, 3), 1))
+            .asset("block", Array3::from_elem((3, 3, 3), 1))
+            .exclude(Exclusion::new("stone", "block").other_rotation(1))
+            .build()
+            .unwrap();
+
+        let stone_id = node_set.asset_bits(&"stone".to_string()).unwrap().first_one().unwrap();
+        assert!(touches(&node_set, stone_id, "stone"));
+        assert!(!touches(&node_set, stone_id, "block"));
+    }
+}
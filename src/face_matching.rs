@@ -0,0 +1,65 @@
+use ndarray::Array3;
+
+/// How the voxels on the faces of assets are compared when deriving sockets.
+/// By default faces only share a socket when every palette index matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaceMatching {
+    classes: Option<Vec<u8>>,
+    tolerance: usize,
+}
+
+impl Default for FaceMatching {
+    fn default() -> Self {
+        Self::exact()
+    }
+}
+
+impl FaceMatching {
+
+    /// Faces match when every palette index matches.
+    pub fn exact() -> Self {
+        Self { classes: None, tolerance: 0 }
+    }
+
+    /// Faces match when the same voxels are filled, whatever their colour.
+    pub fn occupancy() -> Self {
+        let classes = (0..=u8::MAX).map(|index| (index != 0) as u8).collect();
+        Self { classes: Some(classes), tolerance: 0 }
+    }
+
+    /// Faces match when every voxel is in the same class, where each group of palette indices forms one class.
+    /// Indices not in any group only match themselves. Recolours within a group don't create new sockets.
+    pub fn palette_classes(groups: &[&[u8]]) -> Self {
+        let mut classes = (0..=u8::MAX).collect::<Vec<u8>>();
+        for group in groups {
+            let Some(first) = group.iter().min() else { continue; };
+            for index in group.iter() {
+                classes[*index as usize] = *first;
+            }
+        }
+        Self { classes: Some(classes), tolerance: 0 }
+    }
+
+    /// Lets faces that differ by up to `voxels` voxels share a socket, so a stray voxel doesn't break a connection.
+    /// A new face takes the socket of the closest face already seen within the tolerance. Side faces that are their own
+    /// mirror image only take symmetric sockets, so faces that match exactly always still match.
+    pub fn tolerance(mut self, voxels: usize) -> Self {
+        self.tolerance = voxels;
+        self
+    }
+
+    /// The number of differing voxels allowed between faces that share a socket.
+    #[inline]
+    pub(crate) fn allowed_differences(&self) -> usize {
+        self.tolerance
+    }
+
+    /// Replaces every palette index with its class, so faces can be compared exactly.
+    #[inline]
+    pub(crate) fn classify(&self, voxels: &Array3<u8>) -> Array3<u8> {
+        match &self.classes {
+            Some(classes) => voxels.map(|index| classes[*index as usize]),
+            None => voxels.clone(),
+        }
+    }
+}
@@ -1,11 +1,38 @@
+mod error;
+pub use error::WfcError;
+
 mod voxel;
 mod node;
+pub use node::{Node, Neighbors, Orientation, Part, Reflection, Sockets};
+
+mod rules;
+pub use rules::{RuleSet, NodeRule, NeighborIds};
+
+mod adjacency;
+pub use adjacency::Adjacency;
+
+mod exclusion;
+pub use exclusion::Exclusion;
 
 mod node_set;
 pub use node_set::NodeSet;
 
+mod metadata;
+pub use metadata::DeclaredSockets;
+
+mod face_matching;
+pub use face_matching::FaceMatching;
+
+mod node_set_builder;
+pub use node_set_builder::{NodeSetBuilder, Rotations};
+
+mod wave;
+
+mod observer;
+pub use observer::{Observer, NoObserver};
+
 mod wfc;
-pub use wfc::{Solver, Direction};
+pub use wfc::{Solver, Direction, Heuristic, Propagator, Snapshot, Solution, Step};
 
 mod utils;
 pub use utils::*;
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+#[cfg(feature = "toml")]
+use std::fs;
+#[cfg(feature = "toml")]
+use std::path::{Path, PathBuf};
+
+use super::node::{Node, Reflection};
+use super::Direction;
+#[cfg(feature = "toml")]
+use super::WfcError;
+
+/// Sockets named by hand for the faces of an asset as authored, overriding the sockets derived from its voxels.
+/// Side faces follow the naming of derived sockets, ending in `s` when symmetric, or in `f` and `m` for a face and its mirror image.
+/// Top and bottom faces end in `_i` when they look the same in every rotation, or in `_0` to `_3` for each quarter turn.
+/// Faces left as `None` keep their derived socket.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DeclaredSockets {
+    pub px: Option<String>,
+    pub nx: Option<String>,
+    pub py: Option<String>,
+    pub ny: Option<String>,
+    pub pz: Option<String>,
+    pub nz: Option<String>,
+}
+
+impl DeclaredSockets {
+
+    /// Every declared socket along with the face it's declared on.
+    #[inline]
+    fn faces(&self) -> impl Iterator<Item = (Direction, &String)> {
+        [
+            (Direction::POSX, &self.px),
+            (Direction::NEGX, &self.nx),
+            (Direction::POSY, &self.py),
+            (Direction::NEGY, &self.ny),
+            (Direction::POSZ, &self.pz),
+            (Direction::NEGZ, &self.nz),
+        ]
+        .into_iter()
+        .filter_map(|(dir, socket)| socket.as_ref().map(|socket| (dir, socket)))
+    }
+
+    /// Whether a top or bottom socket changes with rotation, so it can't be mirrored.
+    #[inline]
+    pub(crate) fn has_rotating_vert_socket(&self) -> bool {
+        self.faces().any(|(dir, socket)| is_vert(&dir) && !socket.ends_with("_i"))
+    }
+
+    /// Checks that every socket is named so it can be rotated, returning the reason if not.
+    /// `bases` collects the kind of every socket name across assets, so a name can't be symmetric on one face and handed on another.
+    pub(crate) fn validate(&self, bases: &mut HashMap<String, &'static str>) -> Result<(), String> {
+        for (dir, socket) in self.faces() {
+            let (base, kind) = if is_vert(&dir) {
+                match socket.rsplit_once('_') {
+                    Some((base, "i")) => (base, "rotation invariant"),
+                    Some((base, "0" | "1" | "2" | "3")) => (base, "rotating"),
+                    _ => return Err(format!("{:?} socket {:?} must end in _i or _0 to _3", dir, socket)),
+                }
+            } else {
+                match socket.char_indices().last() {
+                    Some((end, 's')) => (&socket[..end], "symmetric"),
+                    Some((end, 'f' | 'm')) => (&socket[..end], "handed"),
+                    _ => return Err(format!("{:?} socket {:?} must end in s, f or m", dir, socket)),
+                }
+            };
+
+            if base.is_empty() {
+                return Err(format!("{:?} socket {:?} needs a name before its suffix", dir, socket));
+            }
+
+            let key = if is_vert(&dir) { format!("{}_", base) } else { base.to_string() };
+            match bases.get(&key) {
+                Some(other) if *other != kind => {
+                    return Err(format!("{:?} socket {:?} is {} but is {} elsewhere", dir, socket, kind, other));
+                },
+                _ => { bases.insert(key, kind); },
+            }
+        }
+        Ok(())
+    }
+
+    /// Overrides the sockets of a `Node`, turning and mirroring them along with the asset.
+    pub(crate) fn apply(&self, node: &mut Node) {
+        for (dir, socket) in self.faces() {
+            let mut socket = socket.clone();
+
+            if is_vert(&dir) {
+                if let Some((base, rotation)) = socket.rsplit_once('_') {
+                    if let Ok(rotation) = rotation.parse::<u8>() {
+                        socket = format!("{}_{}", base, (rotation + node.orientation.turns) % 4);
+                    }
+                }
+            } else if node.reflection != Reflection::None {
+                // A mirrored face is the other hand of the pair
+                let hand = match socket.pop() {
+                    Some('f') => 'm',
+                    Some('m') => 'f',
+                    Some(other) => other,
+                    None => continue,
+                };
+                socket.push(hand);
+            }
+
+            *node.sockets.get_mut(&node.world_direction(&dir)) = socket;
+        }
+    }
+}
+
+#[inline]
+fn is_vert(dir: &Direction) -> bool {
+    matches!(dir, Direction::POSY | Direction::NEGY)
+}
+
+/// The contents of an optional sidecar file next to a voxel file, named after it with `.toml` appended.
+#[cfg(feature = "toml")]
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Sidecar {
+    pub sockets: DeclaredSockets,
+    pub tags: Vec<String>,
+}
+
+/// Reads the sidecar file of a voxel file, if there is one.
+#[cfg(feature = "toml")]
+pub(crate) fn read_sidecar(voxel_path: &Path) -> Result<Option<(PathBuf, Sidecar)>, WfcError> {
+    let mut path = voxel_path.as_os_str().to_owned();
+    path.push(".toml");
+    let path = PathBuf::from(path);
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let text = fs::read_to_string(&path)
+        .map_err(|source| WfcError::Io { path: path.clone(), source })?;
+    let sidecar = toml::from_str::<Sidecar>(&text)
+        .map_err(|error| WfcError::InvalidRules { path: path.clone(), reason: error.message().to_string() })?;
+
+    Ok(Some((path, sidecar)))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+
+    use super::*;
+    use crate::{NodeSet, NodeSetBuilder, Rotations};
+
+    /// Builds every turn of `voxels` along with its mirror image across `X` if `mirrored`, with the sockets given if any.
+    fn build(voxels: Array3<u8>, mirrored: bool, sockets: Option<DeclaredSockets>) -> NodeSet {
+        let mut builder = NodeSetBuilder::new(3).asset("tile", voxels).rotations("tile", Rotations::All);
+        if mirrored {
+            builder = builder.reflection("tile", Reflection::X);
+        }
+        if let Some(sockets) = sockets {
+            builder = builder.sockets("tile", sockets);
+        }
+        builder.build().unwrap()
+    }
+
+    /// Declares the sockets derived for `voxels` as authored on an empty asset, and checks that every turned and
+    /// mirrored `Node` ends up with the same sockets as the one derived from the voxels.
+    fn assert_declared_match_derived(voxels: Array3<u8>, mirrored: bool) {
+        let derived = build(voxels, mirrored, None);
+        let authored = derived.node_dict().values()
+            .find(|node| node.reflection == Reflection::None && node.rotation == 0)
+            .unwrap();
+        let sockets = DeclaredSockets {
+            px: Some(authored.sockets.px.clone()),
+            nx: Some(authored.sockets.nx.clone()),
+            py: Some(authored.sockets.py.clone()),
+            ny: Some(authored.sockets.ny.clone()),
+            pz: Some(authored.sockets.pz.clone()),
+            nz: Some(authored.sockets.nz.clone()),
+        };
+
+        let declared = build(Array3::zeros((3, 3, 3)), mirrored, Some(sockets));
+        assert_eq!(declared.node_dict().len(), derived.node_dict().len());
+        for node in derived.node_dict().values() {
+            let other = declared.node_dict().values()
+                .find(|other| (other.reflection, other.orientation) == (node.reflection, node.orientation))
+                .unwrap();
+            for dir in crate::wfc::DIRECTIONS {
+                assert_eq!(other.sockets.get(dir), node.sockets.get(dir), "{:?} {:?} {:?}", node.reflection, node.orientation, dir);
+            }
+        }
+    }
+
+    #[test]
+    fn declared_sockets_turn_like_derived_sockets() {
+        // Handed side faces and a top face that changes with every quarter turn
+        let mut voxels = Array3::from_elem((3, 3, 3), 1);
+        voxels[[1, 0, 0]] = 0;
+        voxels[[1, 2, 2]] = 0;
+        voxels[[2, 0, 1]] = 0;
+
+        assert_declared_match_derived(voxels, false);
+    }
+
+    #[test]
+    fn declared_sockets_mirror_like_derived_sockets() {
+        let mut voxels = Array3::from_elem((3, 3, 3), 1);
+        voxels[[1, 0, 0]] = 0;
+        voxels[[1, 2, 2]] = 0;
+
+        assert_declared_match_derived(voxels, true);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn sidecar_sockets_are_read_and_validated() {
+        let dir = std::env::temp_dir().join(format!("wfc_metadata_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut xraw = b"XRAW".to_vec();
+        xraw.extend([0, 4, 8, 8]);
+        for len in [3u32, 3, 3, 256] {
+            xraw.extend(len.to_le_bytes());
+        }
+        xraw.extend([1; 27]);
+        fs::write(dir.join("door.xraw"), xraw).unwrap();
+
+        let load = |sidecar: &str| {
+            fs::write(dir.join("door.xraw.toml"), sidecar).unwrap();
+            NodeSetBuilder::new(3).directory(&dir).and_then(|builder| builder.rotations("door", Rotations::None).build())
+        };
+        let valid = load("tags = [\"door\"]\n\n[sockets]\npx = \"doorf\"\npy = \"frame_i\"\n");
+        let invalid = load("[sockets]\npx = \"door\"\n");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let node_set = valid.unwrap();
+        let node = &node_set.node_dict()[&0];
+        assert_eq!(node.sockets.px, "doorf");
+        assert_eq!(node.sockets.py, "frame_i");
+        assert_eq!(node.tags, ["door"]);
+
+        match invalid {
+            Err(WfcError::InvalidRules { path, reason }) => {
+                assert!(path.ends_with("door.xraw.toml"));
+                assert!(reason.contains("must end in s, f or m"), "{}", reason);
+            },
+            _ => panic!("a socket without a suffix should be rejected"),
+        }
+    }
+}
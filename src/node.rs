@@ -1,17 +1,31 @@
 use bitvec::prelude::*;
 
+use super::Direction;
+
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node {
     pub rotation: u8,
+    pub reflection: Reflection,
+    pub orientation: Orientation,
+    pub part: Option<Part>,
+    /// Other reflections and orientations of the asset with the same sockets, which were skipped in favour of this `Node`.
+    pub equivalents: Vec<(Reflection, Orientation)>,
     pub sockets: Sockets,
     pub valid_neighbors: Neighbors,
     pub asset_name: String,
+    pub tags: Vec<String>,
+    pub weight: f64,
 }
 
 impl Node {
-    pub fn new(rotation: u8, asset_name: &String) -> Self {
+    pub fn new(rotation: u8, asset_name: &str) -> Self {
         Self {
             rotation,
+            reflection: Reflection::None,
+            orientation: Orientation { up: Direction::POSY, turns: rotation },
+            part: None,
+            equivalents: Vec::new(),
             sockets: Sockets {
                 px: String::new(),
                 nx: String::new(),
@@ -28,12 +42,118 @@ impl Node {
                 pz: BitVec::new(),
                 nz: BitVec::new(),
             },
-            asset_name: asset_name.clone(),
+            asset_name: asset_name.to_string(),
+            tags: Vec::new(),
+            weight: 1.0,
+        }
+    }
+
+    /// The direction in the map that `dir` of the asset as authored faces, once reflected and oriented like the `Node`.
+    pub fn world_direction(&self, dir: &Direction) -> Direction {
+        world_direction(self.reflection, &self.orientation, dir)
+    }
+
+    /// The reflection and orientation of the `Node`, followed by those of its equivalents.
+    pub(crate) fn poses(&self) -> impl Iterator<Item = (Reflection, Orientation)> + '_ {
+        std::iter::once((self.reflection, self.orientation)).chain(self.equivalents.iter().copied())
+    }
+}
+
+/// The direction in the map that `dir` of an asset as authored faces, once reflected and oriented.
+#[inline]
+pub(crate) fn world_direction(reflection: Reflection, orientation: &Orientation, dir: &Direction) -> Direction {
+    let mut vector: [i8; 3] = match dir {
+        Direction::POSX => [1, 0, 0],
+        Direction::NEGX => [-1, 0, 0],
+        Direction::POSY => [0, 1, 0],
+        Direction::NEGY => [0, -1, 0],
+        Direction::POSZ => [0, 0, 1],
+        Direction::NEGZ => [0, 0, -1],
+    };
+    match reflection {
+        Reflection::None => {},
+        Reflection::X => vector[0] = -vector[0],
+        Reflection::Z => vector[2] = -vector[2],
+    }
+
+    let matrix = orientation.matrix();
+    let turned: [i8; 3] = std::array::from_fn(|i| (0..3).map(|k| matrix[i][k] * vector[k]).sum());
+
+    match turned {
+        [1, 0, 0] => Direction::POSX,
+        [-1, 0, 0] => Direction::NEGX,
+        [0, 1, 0] => Direction::POSY,
+        [0, -1, 0] => Direction::NEGY,
+        [0, 0, 1] => Direction::POSZ,
+        _ => Direction::NEGZ,
+    }
+}
+
+/// The axis an asset is mirrored across to produce a `Node`. Mirroring is applied before rotation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Reflection {
+    #[default]
+    None,
+    X,
+    Z,
+}
+
+/// The orientation of the asset in a `Node`. The asset is turned about its `Y` axis by `turns` quarter turns,
+/// the same as `Node::rotation`, then tipped so its `+Y` axis points `up`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Orientation {
+    pub up: Direction,
+    pub turns: u8,
+}
+
+impl Orientation {
+
+    /// The rotation as a matrix, taking `[x, y, z]` in the asset to `[x, y, z]` in the `Node`. Any reflection is applied first.
+    pub fn matrix(&self) -> [[i8; 3]; 3] {
+        let turn = [[0, 0, 1], [0, 1, 0], [-1, 0, 0]];
+        let tip = match self.up {
+            Direction::POSY => [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+            Direction::NEGY => [[1, 0, 0], [0, -1, 0], [0, 0, -1]],
+            Direction::POSX => [[0, 1, 0], [-1, 0, 0], [0, 0, 1]],
+            Direction::NEGX => [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+            Direction::POSZ => [[1, 0, 0], [0, 0, -1], [0, 1, 0]],
+            Direction::NEGZ => [[1, 0, 0], [0, 0, 1], [0, -1, 0]],
+        };
+
+        let mut ret = tip;
+        for _ in 0..self.turns % 4 {
+            ret = matrix_product(&ret, &turn);
         }
+        ret
     }
 }
 
+/// Where a `Node` sits within an asset spanning several cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Part {
+    /// The position of the cell within the asset as authored, counted in cells along `[x, y, z]`.
+    /// The voxels of the `Node` are that cell of the asset, reflected and oriented like the `Node`.
+    pub offset: [usize; 3],
+    /// Whether another part of the asset must be placed next to the `Node` in each direction, ordered `+X, -X, +Y, -Y, +Z, -Z`.
+    pub joined: [bool; 6],
+}
+
+#[inline]
+fn matrix_product(a: &[[i8; 3]; 3], b: &[[i8; 3]; 3]) -> [[i8; 3]; 3] {
+    let mut ret = [[0; 3]; 3];
+    for (i, row) in ret.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    ret
+}
+
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Neighbors {
     pub px: BitVec,
     pub nx: BitVec,
@@ -43,7 +163,35 @@ pub struct Neighbors {
     pub nz: BitVec,
 }
 
+impl Neighbors {
+
+    /// The valid neighbors in direction `dir`.
+    pub fn get(&self, dir: &Direction) -> &BitVec {
+        match dir {
+            Direction::POSX => &self.px,
+            Direction::NEGX => &self.nx,
+            Direction::POSY => &self.py,
+            Direction::NEGY => &self.ny,
+            Direction::POSZ => &self.pz,
+            Direction::NEGZ => &self.nz,
+        }
+    }
+
+    /// The valid neighbors in direction `dir` as mutable.
+    pub fn get_mut(&mut self, dir: &Direction) -> &mut BitVec {
+        match dir {
+            Direction::POSX => &mut self.px,
+            Direction::NEGX => &mut self.nx,
+            Direction::POSY => &mut self.py,
+            Direction::NEGY => &mut self.ny,
+            Direction::POSZ => &mut self.pz,
+            Direction::NEGZ => &mut self.nz,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sockets {
     pub px: String,
     pub nx: String,
@@ -52,3 +200,30 @@ pub struct Sockets {
     pub pz: String,
     pub nz: String,
 }
+
+impl Sockets {
+
+    /// The socket of the face in direction `dir`.
+    pub fn get(&self, dir: &Direction) -> &String {
+        match dir {
+            Direction::POSX => &self.px,
+            Direction::NEGX => &self.nx,
+            Direction::POSY => &self.py,
+            Direction::NEGY => &self.ny,
+            Direction::POSZ => &self.pz,
+            Direction::NEGZ => &self.nz,
+        }
+    }
+
+    /// The socket of the face in direction `dir` as mutable.
+    pub fn get_mut(&mut self, dir: &Direction) -> &mut String {
+        match dir {
+            Direction::POSX => &mut self.px,
+            Direction::NEGX => &mut self.nx,
+            Direction::POSY => &mut self.py,
+            Direction::NEGY => &mut self.ny,
+            Direction::POSZ => &mut self.pz,
+            Direction::NEGZ => &mut self.nz,
+        }
+    }
+}
@@ -1,49 +1,122 @@
 use std::collections::{HashMap, HashSet};
+#[cfg(feature = "ron")]
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use bitvec::prelude::*;
 
-use super::voxel;
-use super::node::Node;
+use super::adjacency::dir_index;
+use super::node::{world_direction, Node, Orientation, Part, Reflection};
+use super::{Adjacency, Direction, NodeSetBuilder, RuleSet, WfcError};
 
 /// Contains mapping to all `Node`s and asset bit masks. `Node`s contain rules and metadata for each asset rotation.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NodeSet {
     node_dict: HashMap<usize, Node>,
+    adjacency: Arc<Adjacency>,
     bit_mask: BitVec,
     asset_bits: HashMap<String, BitVec>,
+    tag_bits: HashMap<String, BitVec>,
 }
 
 impl NodeSet {
     
     /// Creates a new `NodeSet` instance given the directory to all voxel files. A `Node` is generated for each voxel file rotation around the `Y` axis.
-    /// `node_size` is the array length of each voxel file. This should be uniform across dimensions and voxel files.
+    /// `node_size` is the array length of a single cell in every dimension. Voxel files may span any whole number of cells
+    /// along each axis, and are split into parts that are always placed together.
     /// `exclusions` is the list of asset mappings that you don't want connected.
-    pub fn new(node_size: usize, directory: String, exclusions: HashSet<(&str, &str)>) -> NodeSet {
-        let node_dict = voxel::node_dict_from_directory(&directory, [node_size, node_size, node_size], &exclusions);
-        
+    /// Returns an error naming the offending path if the directory or any voxel file in it cannot be loaded.
+    /// Use `NodeSetBuilder` to build from voxel arrays in memory or from assets that aren't cubic instead.
+    pub fn new<P: AsRef<Path>>(node_size: usize, directory: P, exclusions: HashSet<(&str, &str)>) -> Result<NodeSet, WfcError> {
+        let mut builder = NodeSetBuilder::new(node_size).directory(directory)?;
+        for (asset, other) in exclusions {
+            builder = builder.exclusion(asset, other);
+        }
+
+        builder.build()
+    }
+    
+    /// Creates a new `NodeSet` instance from a `RuleSet`, without loading any voxel files.
+    /// Returns an error if node ids aren't unique and contiguous from 0, or a neighbor id doesn't exist.
+    pub fn from_rules(rules: &RuleSet) -> Result<NodeSet, WfcError> {
+        let node_dict = rules.to_node_dict()
+            .map_err(|reason| WfcError::InvalidRules { path: PathBuf::new(), reason })?;
+
+        Ok(Self::from_node_dict(node_dict))
+    }
+    
+    /// Creates a new `NodeSet` instance from a rule file written by `export_rules`.
+    #[cfg(feature = "ron")]
+    pub fn import_rules<P: AsRef<Path>>(path: P) -> Result<NodeSet, WfcError> {
+        let path = path.as_ref();
+        let invalid_rules = |reason: String| WfcError::InvalidRules { path: path.to_path_buf(), reason };
+
+        let text = fs::read_to_string(path)
+            .map_err(|source| WfcError::Io { path: path.to_path_buf(), source })?;
+        let rules: RuleSet = ron::from_str(&text)
+            .map_err(|error| invalid_rules(error.to_string()))?;
+        let node_dict = rules.to_node_dict()
+            .map_err(invalid_rules)?;
+
+        Ok(Self::from_node_dict(node_dict))
+    }
+    
+    /// Writes the rules of every `Node` to a human-readable RON file, which can be edited and loaded with `import_rules`.
+    #[cfg(feature = "ron")]
+    pub fn export_rules<P: AsRef<Path>>(&self, path: P) -> Result<(), WfcError> {
+        let path = path.as_ref();
+
+        let text = ron::ser::to_string_pretty(&self.rules(), ron::ser::PrettyConfig::new().compact_arrays(true))
+            .map_err(|error| WfcError::InvalidRules { path: path.to_path_buf(), reason: error.to_string() })?;
+
+        fs::write(path, text)
+            .map_err(|source| WfcError::Io { path: path.to_path_buf(), source })
+    }
+    
+    pub(crate) fn from_node_dict(node_dict: HashMap<usize, Node>) -> NodeSet {
         let mut asset_bits = HashMap::new();
+        let mut tag_bits = HashMap::<String, BitVec>::new();
         let mut bit_mask = BitVec::new();
         bit_mask.resize(node_dict.len(), false);
         
         for (id, node) in &node_dict {
             asset_bits.entry(node.asset_name.clone()).or_insert(bit_mask.clone());
             asset_bits.get_mut(&node.asset_name).unwrap().set(*id, true);
+
+            for tag in &node.tags {
+                tag_bits.entry(tag.clone()).or_insert_with(|| bit_mask.clone()).set(*id, true);
+            }
         }
         
         bit_mask.fill(true);
 
         NodeSet {
+            adjacency: Arc::new(Adjacency::from_node_dict(&node_dict)),
             node_dict,
             asset_bits,
+            tag_bits,
             bit_mask,
         }
     }
     
+    /// The rules of every `Node` in a readable form, with neighbors listed by node id.
+    pub fn rules(&self) -> RuleSet {
+        RuleSet::from_node_dict(&self.node_dict)
+    }
+    
     /// The dictionary to every `Node`.
     pub fn node_dict(&self) -> &HashMap<usize, Node> {
         &self.node_dict
     }
     
+    /// The valid neighbors of every `Node`, indexed by node id and direction.
+    /// This is shared with every `Solver` created from this `NodeSet`.
+    pub fn adjacency(&self) -> &Arc<Adjacency> {
+        &self.adjacency
+    }
+    
     /// A bit mask in the length of all `Node`s.
     pub fn bit_mask(&self) -> &BitVec {
         &self.bit_mask
@@ -57,6 +130,95 @@ impl NodeSet {
         None
     }
     
+    /// The full orientation of a `Node`, including which way its asset's `+Y` axis points.
+    pub fn get_orientation(&self, node_id: &usize) -> Option<Orientation> {
+        self.node_dict.get(node_id).map(|node| node.orientation)
+    }
+    
+    /// Where a `Node` sits within its asset, if the asset spans several cells.
+    pub fn get_part(&self, node_id: &usize) -> Option<Part> {
+        self.node_dict.get(node_id).and_then(|node| node.part)
+    }
+    
+    /// The axis the asset of a `Node` is mirrored across, if any.
+    pub fn get_reflection(&self, node_id: &usize) -> Option<Reflection> {
+        self.node_dict.get(node_id).map(|node| node.reflection)
+    }
+    
+    /// The weight of a `Node` when the solver picks between the remaining options of a cell.
+    pub fn get_weight(&self, node_id: &usize) -> Option<f64> {
+        if let Some(node) = self.node_dict.get(node_id) {
+            return Some(node.weight);
+        }
+        None
+    }
+    
+    /// Set the weight of a single `Node`, allowing rotations of the same asset to appear at different frequencies.
+    /// Returns `false` if the `Node` doesn't exist or the weight is negative or not finite.
+    pub fn set_weight(&mut self, node_id: &usize, weight: &f64) -> bool {
+        if !weight.is_finite() || *weight < 0.0 { return false; }
+
+        if let Some(node) = self.node_dict.get_mut(node_id) {
+            node.weight = *weight;
+            return true;
+        }
+        false
+    }
+    
+    /// Set the weight of an asset, split evenly between its orientations so assets with more orientations aren't picked more often.
+    /// `Node`s standing in for orientations skipped as duplicates take a share for each orientation they stand in for.
+    /// `Node` weights default to 1 for each orientation. Returns `false` if the asset doesn't exist or the weight is negative or not finite.
+    pub fn set_asset_weight(&mut self, asset: &String, weight: &f64) -> bool {
+        if !weight.is_finite() || *weight < 0.0 { return false; }
+
+        let Some(bits) = self.asset_bits.get(asset) else { return false; };
+        let poses: usize = bits.iter_ones().map(|id| self.node_dict[&id].poses().count()).sum();
+        for id in bits.iter_ones() {
+            let node = self.node_dict.get_mut(&id).unwrap();
+            node.weight = *weight * node.poses().count() as f64 / poses as f64;
+        }
+        true
+    }
+    
+    /// Allows `other` to be placed next to `asset` in `directions`, on top of the neighbors matched by their sockets.
+    /// Directions are given from `asset` as authored, and every orientation of `asset` is allowed next to the same
+    /// orientation of `other` in the matching turned direction. `Node`s standing in for orientations skipped as duplicates
+    /// take part for each orientation they stand in for. Faces joined to another part of the same asset are left as is.
+    /// Returns `false` if either asset doesn't exist. `Solver`s created earlier keep the rules they were created with.
+    pub fn allow(&mut self, asset: &String, other: &String, directions: &[Direction]) -> bool {
+        let (Some(asset_bits), Some(other_bits)) = (self.asset_bits.get(asset), self.asset_bits.get(other)) else { return false; };
+
+        let mut allowed = Vec::<(usize, usize, Direction)>::new();
+        for other_id in other_bits.iter_ones() {
+            let other_node = &self.node_dict[&other_id];
+
+            for (reflection, orientation) in other_node.poses() {
+                for id in asset_bits.iter_ones() {
+                    let node = &self.node_dict[&id];
+                    if !node.poses().any(|pose| pose == (reflection, orientation)) { continue; }
+
+                    for dir in directions {
+                        let world_dir = world_direction(reflection, &orientation, dir);
+                        let joined = node.part.is_some_and(|part| part.joined[dir_index(&world_dir)])
+                            || other_node.part.is_some_and(|part| part.joined[dir_index(&world_dir.opposite())]);
+
+                        if !joined {
+                            allowed.push((id, other_id, world_dir));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (id, other_id, dir) in allowed {
+            self.node_dict.get_mut(&id).unwrap().valid_neighbors.get_mut(&dir).set(other_id, true);
+            self.node_dict.get_mut(&other_id).unwrap().valid_neighbors.get_mut(&dir.opposite()).set(id, true);
+        }
+
+        self.adjacency = Arc::new(Adjacency::from_node_dict(&self.node_dict));
+        true
+    }
+    
     /// The name of the asset a `Node` represents.
     pub fn get_asset_name(&self, node_id: &usize) -> Option<&String> {
         if let Some(node) = self.node_dict.get(node_id) {
@@ -69,4 +231,189 @@ impl NodeSet {
     pub fn asset_bits(&self, asset: &String) -> Option<&BitVec> {
         self.asset_bits.get(asset)
     }
+    
+    /// The bit mask for all the `Node`s of assets with a tag.
+    pub fn tag_bits(&self, tag: &str) -> Option<&BitVec> {
+        self.tag_bits.get(tag)
+    }
+    
+    /// Every tag given to at least one asset.
+    pub fn tags(&self) -> impl Iterator<Item = &String> {
+        self.tag_bits.keys()
+    }
+    
+    /// A bit mask of the `Node`s with any of the given tags, to be used with `Solver::constrain_list`.
+    /// `constrain_list` bans the `Node`s in the mask, so pass its inverse to keep only them. Tags that no asset has are ignored.
+    pub fn tags_union(&self, tags: &[&str]) -> BitVec {
+        let mut ret = bitvec![0; self.bit_mask.len()];
+        for bits in tags.iter().filter_map(|tag| self.tag_bits.get(*tag)) {
+            ret |= bits;
+        }
+        ret
+    }
+    
+    /// A bit mask of the `Node`s with all of the given tags, to be used with `Solver::constrain_list`.
+    /// Every `Node` is in the mask when no tags are given.
+    pub fn tags_intersection(&self, tags: &[&str]) -> BitVec {
+        let mut ret = self.bit_mask.clone();
+        for tag in tags {
+            match self.tag_bits.get(*tag) {
+                Some(bits) => ret &= bits,
+                None => ret.fill(false),
+            }
+        }
+        ret
+    }
+    
+    /// A bit mask of the `Node`s with any of the tags in `include` and none of the tags in `exclude`,
+    /// to be used with `Solver::constrain_list`.
+    pub fn tags_difference(&self, include: &[&str], exclude: &[&str]) -> BitVec {
+        let mut ret = self.tags_union(include);
+        ret &= !self.tags_union(exclude);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+
+    use super::*;
+    use crate::Solver;
+
+    #[test]
+    fn asset_weights_are_split_between_orientations() {
+        let mut corner = Array3::from_elem((3, 3, 3), 2);
+        corner[[1, 0, 0]] = 0;
+
+        let mut node_set = NodeSetBuilder::new(3)
+            .asset("full", Array3::from_elem((3, 3, 3), 1))
+            .asset("corner", corner)
+            .build()
+            .unwrap();
+
+        let total = |node_set: &NodeSet, asset: &str| -> f64 {
+            node_set.asset_bits(&asset.to_string()).unwrap().iter_ones().map(|id| node_set.get_weight(&id).unwrap()).sum()
+        };
+
+        assert!(node_set.set_asset_weight(&"full".to_string(), &2.0));
+        assert!(node_set.set_asset_weight(&"corner".to_string(), &2.0));
+        assert_eq!(total(&node_set, "full"), 2.0);
+        assert_eq!(total(&node_set, "corner"), 2.0);
+        assert!(!node_set.set_asset_weight(&"corner".to_string(), &-1.0));
+    }
+
+    #[test]
+    fn default_weights_pick_assets_evenly() {
+        let mut corner = Array3::from_elem((3, 3, 3), 2);
+        corner[[1, 0, 0]] = 0;
+
+        let node_set = NodeSetBuilder::new(3)
+            .asset("full", Array3::from_elem((3, 3, 3), 1))
+            .asset("corner", corner)
+            .build()
+            .unwrap();
+        assert_eq!(node_set.asset_bits(&"full".to_string()).unwrap().count_ones(), 1);
+
+        let picks = 2000;
+        let full = (0..picks)
+            .filter(|seed| {
+                let map = Solver::from_seed([1, 1, 1], node_set.bit_mask(), &node_set, seed).solve().unwrap();
+                node_set.get_asset_name(&map[[0, 0, 0]]).unwrap() == "full"
+            })
+            .count();
+        assert!((900..1100).contains(&full), "full picked {} of {} times", full, picks);
+    }
+
+    #[test]
+    fn allow_covers_orientations_skipped_as_duplicates() {
+        let full = Array3::from_elem((3, 3, 3), 1);
+        let mut corner = Array3::from_elem((3, 3, 3), 2);
+        corner[[1, 0, 0]] = 0;
+
+        let mut node_set = NodeSetBuilder::new(3)
+            .asset("full", full)
+            .asset("corner", corner)
+            .build()
+            .unwrap();
+
+        let (full, corner) = ("full".to_string(), "corner".to_string());
+        let full_id = node_set.asset_bits(&full).unwrap().first_one().unwrap();
+        assert_eq!(node_set.asset_bits(&full).unwrap().count_ones(), 1);
+        assert_eq!(node_set.asset_bits(&corner).unwrap().count_ones(), 4);
+
+        assert!(node_set.allow(&full, &corner, &[Direction::POSX]));
+
+        for corner_id in node_set.asset_bits(&corner).unwrap().iter_ones() {
+            let dir = node_set.node_dict()[&corner_id].world_direction(&Direction::POSX);
+            assert!(node_set.node_dict()[&full_id].valid_neighbors.get(&dir)[corner_id]);
+            assert!(node_set.node_dict()[&corner_id].valid_neighbors.get(&dir.opposite())[full_id]);
+        }
+    }
+
+    /// A tile set touching every field of the rules, with mirrored, merged, multi-cell and tagged `Node`s.
+    fn varied_node_set() -> NodeSet {
+        let mut corner = Array3::from_elem((3, 3, 3), 2);
+        corner[[1, 0, 0]] = 0;
+
+        let mut node_set = NodeSetBuilder::new(3)
+            .asset("full", Array3::from_elem((3, 3, 3), 1))
+            .asset("corner", corner)
+            .asset("wide", Array3::from_elem((3, 6, 3), 1))
+            .reflection("corner", Reflection::X)
+            .tag("full", "solid")
+            .tag("wide", "solid")
+            .build()
+            .unwrap();
+        assert!(node_set.set_asset_weight(&"corner".to_string(), &0.3));
+        assert!(node_set.allow(&"full".to_string(), &"corner".to_string(), &[Direction::POSY]));
+        node_set
+    }
+
+    #[test]
+    fn tag_masks_combine_tags() {
+        let node_set = NodeSetBuilder::new(3)
+            .asset("grass", Array3::from_elem((3, 3, 3), 1))
+            .asset("mud", Array3::from_elem((3, 3, 3), 2))
+            .asset("water", Array3::from_elem((3, 3, 3), 3))
+            .asset("air", Array3::zeros((3, 3, 3)))
+            .tag("grass", "walkable")
+            .tag("mud", "walkable")
+            .tag("mud", "wet")
+            .tag("water", "wet")
+            .build()
+            .unwrap();
+        let assets = |bits: BitVec| {
+            let mut names = bits.iter_ones().map(|id| node_set.get_asset_name(&id).unwrap().as_str()).collect::<Vec<&str>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(assets(node_set.tag_bits("wet").unwrap().clone()), ["mud", "water"]);
+        assert_eq!(assets(node_set.tags_union(&["walkable", "wet"])), ["grass", "mud", "water"]);
+        assert_eq!(assets(node_set.tags_intersection(&["walkable", "wet"])), ["mud"]);
+        assert_eq!(assets(node_set.tags_difference(&["walkable"], &["wet"])), ["grass"]);
+        assert_eq!(assets(node_set.tags_difference(&["walkable", "missing"], &["missing"])), ["grass", "mud"]);
+        assert!(node_set.tags_intersection(&["walkable", "missing"]).not_any());
+    }
+
+    #[test]
+    fn rules_round_trip() {
+        let node_set = varied_node_set();
+        let rules = node_set.rules();
+        assert!(NodeSet::from_rules(&rules).unwrap().rules() == rules);
+    }
+
+    #[cfg(feature = "ron")]
+    #[test]
+    fn exported_rules_round_trip() {
+        let node_set = varied_node_set();
+        let path = std::env::temp_dir().join(format!("wfc_rules_{}.ron", std::process::id()));
+
+        node_set.export_rules(&path).unwrap();
+        let imported = NodeSet::import_rules(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(imported.unwrap().rules() == node_set.rules());
+    }
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use ndarray::Array3;
+
+#[cfg(feature = "toml")]
+use super::metadata;
+use super::voxel::{self, Asset};
+use super::{DeclaredSockets, Direction, Exclusion, FaceMatching, NodeSet, Reflection, WfcError};
+
+/// The rotations about the `Y` axis to generate `Node`s for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rotations {
+    /// Only the asset as authored, for assets that must never turn, like signage.
+    None,
+    /// The asset as authored and turned by 180 degrees.
+    Half,
+    /// All four quarter turns, even those with the same sockets as another. Needs a node as wide as it is deep.
+    All,
+    /// All four quarter turns, skipping any with the same sockets as an earlier one, including mirrored variants.
+    /// The `Node` kept is weighted once for each turn it stands in for, so the asset is picked as often as with `All`.
+    /// Only half turns are generated when the node isn't as wide as it is deep.
+    #[default]
+    Auto,
+    /// All 24 orientations of the cube, for assets that can lie on their side like pipes or cables.
+    /// Skips any with the same sockets as an earlier one. Needs a cubic node.
+    Cube,
+}
+
+impl Rotations {
+
+    /// Whether a `Node` is generated for the given number of quarter turns.
+    #[inline]
+    pub(crate) fn allows(&self, rotation: u8) -> bool {
+        match self {
+            Rotations::None => rotation == 0,
+            Rotations::Half => rotation == 0 || rotation == 2,
+            Rotations::All | Rotations::Auto | Rotations::Cube => true,
+        }
+    }
+
+    /// Whether every rotation asked for keeps a node of the given `[width, height, depth]` the same shape.
+    #[inline]
+    pub(crate) fn fits(&self, node_shape: [usize; 3]) -> bool {
+        let [width, height, depth] = node_shape;
+        match self {
+            Rotations::All => width == depth,
+            Rotations::Cube => width == depth && width == height,
+            _ => true,
+        }
+    }
+
+    /// Whether `Node`s with the same sockets as an earlier `Node` of the same asset are skipped.
+    #[inline]
+    pub(crate) fn skips_duplicates(&self) -> bool {
+        matches!(self, Rotations::Auto | Rotations::Cube)
+    }
+
+    /// The directions an asset's `+Y` axis may point in.
+    #[inline]
+    pub(crate) fn ups(&self) -> &'static [Direction] {
+        match self {
+            Rotations::Cube => &[Direction::POSY, Direction::NEGY, Direction::POSX, Direction::NEGX, Direction::POSZ, Direction::NEGZ],
+            _ => &[Direction::POSY],
+        }
+    }
+}
+
+/// Builds a `NodeSet` from voxel arrays in memory, voxel files, or both.
+/// Every asset goes through the same socket generation, rotation and neighbor matching as `NodeSet::new`.
+#[derive(Clone)]
+pub struct NodeSetBuilder {
+    node_shape: [usize; 3],
+    assets: Vec<(String, Array3<u8>)>,
+    exclusions: Vec<Exclusion>,
+    rotations: HashMap<String, Rotations>,
+    default_rotations: Rotations,
+    reflections: HashMap<String, Vec<Reflection>>,
+    sockets: HashMap<String, (PathBuf, DeclaredSockets)>,
+    tags: HashMap<String, Vec<String>>,
+    face_matching: FaceMatching,
+}
+
+impl NodeSetBuilder {
+
+    /// Creates an empty builder. `node_size` is the array length of each asset in every dimension.
+    pub fn new(node_size: usize) -> Self {
+        Self::from_shape([node_size, node_size, node_size])
+    }
+
+    /// Creates an empty builder for assets that aren't cubic, with `node_shape` given as `[width, height, depth]`
+    /// in the same `[x, y, z]` order as the map.
+    pub fn from_shape(node_shape: [usize; 3]) -> Self {
+        Self {
+            node_shape,
+            assets: Vec::new(),
+            exclusions: Vec::new(),
+            rotations: HashMap::new(),
+            default_rotations: Rotations::default(),
+            reflections: HashMap::new(),
+            sockets: HashMap::new(),
+            tags: HashMap::new(),
+            face_matching: FaceMatching::default(),
+        }
+    }
+
+    /// Adds an asset from a voxel array of palette indices, where 0 is empty.
+    /// The array is indexed `[y, x, z]` with `Y` pointing up, the same layout as voxel files loaded from disk,
+    /// so its shape is `[height, width, depth]`. Arrays spanning several cells are split into one `Node` per cell,
+    /// joined so the solver places the whole asset or none of it.
+    pub fn asset(mut self, asset_name: &str, voxels: Array3<u8>) -> Self {
+        self.assets.push((asset_name.to_string(), voxels));
+        self
+    }
+
+    /// Adds an asset for every voxel file in a directory. Like arrays added with `asset`, files spanning several cells are split into parts.
+    /// With the `toml` feature, sockets and tags are also read from a sidecar file named after the voxel file, like `tile.xraw.toml`.
+    /// Returns an error naming the offending path if the directory or any voxel file or sidecar in it cannot be loaded.
+    pub fn directory<P: AsRef<Path>>(mut self, directory: P) -> Result<Self, WfcError> {
+        for (path, asset_name, voxels) in voxel::assets_from_directory(directory.as_ref(), self.node_shape)? {
+            #[cfg(feature = "toml")]
+            if let Some((sidecar_path, sidecar)) = metadata::read_sidecar(&path)? {
+                for tag in &sidecar.tags {
+                    self = self.tag(&asset_name, tag);
+                }
+                self.sockets.insert(asset_name.clone(), (sidecar_path, sidecar.sockets));
+            }
+            #[cfg(not(feature = "toml"))]
+            let _ = path;
+
+            self.assets.push((asset_name, voxels));
+        }
+        Ok(self)
+    }
+
+    /// Prevents `other` from being placed next to `asset` in any direction.
+    pub fn exclusion(self, asset: &str, other: &str) -> Self {
+        self.exclude(Exclusion::new(asset, other))
+    }
+
+    /// Adds an exclusion rule, which can be limited to some directions or rotations.
+    pub fn exclude(mut self, exclusion: Exclusion) -> Self {
+        self.exclusions.push(exclusion);
+        self
+    }
+
+    /// Sets the rotations generated for an asset, whether it was added from an array or a directory.
+    pub fn rotations(mut self, asset_name: &str, rotations: Rotations) -> Self {
+        self.rotations.insert(asset_name.to_string(), rotations);
+        self
+    }
+
+    /// Sets the rotations generated for assets without their own, `Rotations::Auto` unless changed.
+    pub fn default_rotations(mut self, rotations: Rotations) -> Self {
+        self.default_rotations = rotations;
+        self
+    }
+
+    /// Also generates `Node`s for an asset mirrored across `axis`, for assets with a left and right handed form.
+    /// The mirrored asset is rotated the same way as the asset itself.
+    pub fn reflection(mut self, asset_name: &str, axis: Reflection) -> Self {
+        self.reflections.entry(asset_name.to_string()).or_default().push(axis);
+        self
+    }
+
+    /// Names the sockets of some faces of an asset by hand instead of deriving them from its voxels.
+    /// Declared sockets are turned and mirrored along with the asset, and are matched with derived sockets of the same name.
+    pub fn sockets(mut self, asset_name: &str, sockets: DeclaredSockets) -> Self {
+        self.sockets.insert(asset_name.to_string(), (PathBuf::from(asset_name), sockets));
+        self
+    }
+
+    /// Tags an asset, so masks of every `Node` with a tag can be taken from the `NodeSet` with `NodeSet::tag_bits`.
+    pub fn tag(mut self, asset_name: &str, tag: &str) -> Self {
+        let tags = self.tags.entry(asset_name.to_string()).or_default();
+        if !tags.iter().any(|other| other == tag) {
+            tags.push(tag.to_string());
+        }
+        self
+    }
+
+    /// Sets how the faces of assets are compared when deriving sockets, exact palette indices unless changed.
+    pub fn face_matching(mut self, face_matching: FaceMatching) -> Self {
+        self.face_matching = face_matching;
+        self
+    }
+
+    /// Builds the `NodeSet`. A `Node` is generated for each allowed asset rotation around the `Y` axis.
+    /// Returns an error naming the asset if an array added with `asset` doesn't cover a whole number of cells,
+    /// or an asset asks for rotations that would change its shape.
+    /// Returns an error naming the sidecar or asset if declared sockets can't be turned or mirrored consistently,
+    /// or two assets share a name.
+    pub fn build(mut self) -> Result<NodeSet, WfcError> {
+        let mut socket_bases = HashMap::new();
+        for (i, (asset_name, voxels)) in self.assets.iter().enumerate() {
+            if self.assets[..i].iter().any(|(other_name, _)| other_name == asset_name) {
+                return Err(WfcError::DuplicateAsset { path: PathBuf::from(asset_name), name: asset_name.clone() });
+            }
+            if !voxel::fits_node_shape(voxels, self.node_shape) {
+                let found = voxel::node_shape_of(voxels);
+                return Err(WfcError::ShapeMismatch { path: PathBuf::from(asset_name), expected: self.node_shape, found });
+            }
+
+            let rotations = self.rotations.get(asset_name).copied().unwrap_or(self.default_rotations);
+            if !rotations.fits(self.node_shape) {
+                return Err(WfcError::UnsupportedRotations { path: PathBuf::from(asset_name), rotations, shape: self.node_shape });
+            }
+
+            if let Some((path, sockets)) = self.sockets.get(asset_name) {
+                let invalid_sockets = |reason: &str| WfcError::InvalidRules { path: path.clone(), reason: reason.to_string() };
+
+                sockets.validate(&mut socket_bases).map_err(|reason| invalid_sockets(&reason))?;
+                if voxel::node_shape_of(voxels) != self.node_shape {
+                    return Err(invalid_sockets("sockets can't be declared for assets spanning several cells"));
+                }
+                if rotations == Rotations::Cube {
+                    return Err(invalid_sockets("sockets can't be declared for assets using cube rotations"));
+                }
+                if self.reflections.contains_key(asset_name) && sockets.has_rotating_vert_socket() {
+                    return Err(invalid_sockets("mirrored assets can only declare top and bottom sockets ending in _i"));
+                }
+            }
+        }
+
+        let assets = self.assets.into_iter()
+            .map(|(name, voxels)| Asset {
+                rotations: self.rotations.get(&name).copied().unwrap_or(self.default_rotations),
+                reflections: self.reflections.get(&name).cloned().unwrap_or_default(),
+                sockets: self.sockets.remove(&name).map(|(_, sockets)| sockets),
+                tags: self.tags.get(&name).cloned().unwrap_or_default(),
+                name,
+                voxels,
+            })
+            .collect::<Vec<Asset>>();
+
+        Ok(NodeSet::from_node_dict(voxel::node_dict_from_assets(&assets, self.node_shape, &self.exclusions, &self.face_matching)))
+    }
+}
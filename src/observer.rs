@@ -0,0 +1,24 @@
+/// Receives events from a `Solver` as it solves, e.g. to drive progress bars, logs or live visualisations.
+/// Every method does nothing by default, so only the events of interest need to be implemented.
+pub trait Observer {
+    /// Called after the cell at `pos` is collapsed to `node_id`, before the result is propagated.
+    fn on_collapse(&mut self, _pos: &[usize; 3], _node_id: usize) {}
+
+    /// Called whenever `node_id` is removed from the options of the cell at `pos`, outside of collapses.
+    fn on_ban(&mut self, _pos: &[usize; 3], _node_id: usize) {}
+
+    /// Called after a contradiction at `pos` is undone by restoring an earlier state.
+    fn on_backtrack(&mut self, _pos: &[usize; 3]) {}
+
+    /// Called when a step reports that the cell at `pos` has no options left.
+    fn on_contradiction(&mut self, _pos: &[usize; 3]) {}
+
+    /// Called when a step reports that every cell has been collapsed.
+    fn on_complete(&mut self) {}
+}
+
+/// The default `Observer` of a `Solver`, which ignores every event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
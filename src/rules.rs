@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use bitvec::prelude::*;
+
+use super::node::{Node, Orientation, Part, Reflection, Sockets};
+use super::Direction;
+
+/// A readable form of the rules in a `NodeSet`, listing every `Node` with its sockets and allowed neighbors.
+/// With the `ron` feature this can be written to and read from a text file to audit or hand-edit the rules.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleSet {
+    pub nodes: Vec<NodeRule>,
+}
+
+/// The rules for a single `Node`. Neighbors are listed by node id.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeRule {
+    pub id: usize,
+    pub asset_name: String,
+    pub rotation: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reflection: Reflection,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub up: Direction,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub part: Option<Part>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub equivalents: Vec<(Reflection, Orientation)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: Vec<String>,
+    pub weight: f64,
+    pub sockets: Sockets,
+    pub neighbors: NeighborIds,
+}
+
+/// The ids of the `Node`s allowed next to a `Node` in each direction.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborIds {
+    pub px: Vec<usize>,
+    pub nx: Vec<usize>,
+    pub py: Vec<usize>,
+    pub ny: Vec<usize>,
+    pub pz: Vec<usize>,
+    pub nz: Vec<usize>,
+}
+
+impl RuleSet {
+
+    /// Lists the rules of every `Node`, ordered by node id.
+    pub fn from_node_dict(node_dict: &HashMap<usize, Node>) -> Self {
+        let mut nodes = node_dict.iter()
+            .map(|(id, node)| NodeRule {
+                id: *id,
+                asset_name: node.asset_name.clone(),
+                rotation: node.rotation,
+                reflection: node.reflection,
+                up: node.orientation.up,
+                part: node.part,
+                equivalents: node.equivalents.clone(),
+                tags: node.tags.clone(),
+                weight: node.weight,
+                sockets: node.sockets.clone(),
+                neighbors: NeighborIds {
+                    px: node.valid_neighbors.px.iter_ones().collect(),
+                    nx: node.valid_neighbors.nx.iter_ones().collect(),
+                    py: node.valid_neighbors.py.iter_ones().collect(),
+                    ny: node.valid_neighbors.ny.iter_ones().collect(),
+                    pz: node.valid_neighbors.pz.iter_ones().collect(),
+                    nz: node.valid_neighbors.nz.iter_ones().collect(),
+                },
+            })
+            .collect::<Vec<NodeRule>>();
+
+        nodes.sort_by_key(|node| node.id);
+
+        Self { nodes }
+    }
+
+    /// Builds the `Node`s described by the rules.
+    /// Returns the reason if node ids aren't unique and contiguous from 0, or a neighbor id doesn't exist.
+    pub fn to_node_dict(&self) -> Result<HashMap<usize, Node>, String> {
+        let len = self.nodes.len();
+        let mut ret = HashMap::<usize, Node>::new();
+
+        for rule in &self.nodes {
+            if rule.id >= len {
+                return Err(format!("node id {} is out of range for {} nodes", rule.id, len));
+            }
+            if !rule.weight.is_finite() || rule.weight < 0.0 {
+                return Err(format!("node {} has invalid weight {}", rule.id, rule.weight));
+            }
+
+            let mut node = Node::new(rule.rotation, &rule.asset_name);
+            node.reflection = rule.reflection;
+            node.orientation.up = rule.up;
+            node.part = rule.part;
+            node.equivalents = rule.equivalents.clone();
+            node.tags = rule.tags.clone();
+            node.weight = rule.weight;
+            node.sockets = rule.sockets.clone();
+
+            let lists = [
+                (&mut node.valid_neighbors.px, &rule.neighbors.px),
+                (&mut node.valid_neighbors.nx, &rule.neighbors.nx),
+                (&mut node.valid_neighbors.py, &rule.neighbors.py),
+                (&mut node.valid_neighbors.ny, &rule.neighbors.ny),
+                (&mut node.valid_neighbors.pz, &rule.neighbors.pz),
+                (&mut node.valid_neighbors.nz, &rule.neighbors.nz),
+            ];
+
+            for (bits, ids) in lists {
+                *bits = BitVec::repeat(false, len);
+                for id in ids {
+                    if *id >= len {
+                        return Err(format!("node {} lists neighbor {} which doesn't exist", rule.id, id));
+                    }
+                    bits.set(*id, true);
+                }
+            }
+
+            if ret.insert(rule.id, node).is_some() {
+                return Err(format!("node id {} is listed more than once", rule.id));
+            }
+        }
+
+        Ok(ret)
+    }
+}
@@ -1,15 +1,56 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs;
-use std::str;
 use std::io::Read;
 use std::io::BufReader;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 
 use ndarray::{Array3, Array2, Axis, s};
 
-use crate::node::{Node, Sockets};
+use crate::node::{Node, Part, Reflection, Sockets};
+use crate::{DeclaredSockets, Direction, Exclusion, FaceMatching, Rotations, WfcError};
+
+/// A voxel array along with the rotations and mirrored variants to generate `Node`s for.
+pub struct Asset {
+    pub name: String,
+    pub voxels: Array3<u8>,
+    pub rotations: Rotations,
+    pub reflections: Vec<Reflection>,
+    pub sockets: Option<DeclaredSockets>,
+    pub tags: Vec<String>,
+}
+
+/// Loads every voxel file in a directory, checking that each covers a whole number of cells of `node_shape`.
+/// Assets are returned with the path of the file they were loaded from.
+pub fn assets_from_directory(asset_dir: &Path, node_shape: [usize; 3]) -> Result<Vec<(PathBuf, String, Array3<u8>)>, WfcError> {
+    let io_error = |source| WfcError::Io { path: asset_dir.to_path_buf(), source };
+    let mut paths = fs::read_dir(asset_dir).map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .map_err(io_error)?;
+
+    // Sockets are numbered in the order faces are first seen, which shouldn't depend on the file system
+    paths.sort();
+
+    let mut assets = Vec::<(PathBuf, String, Array3<u8>)>::new();
+    for entry_path in paths {
+        for (asset_name, vox_array) in vox_arrays_from_path(&entry_path)? {
+            if !fits_node_shape(&vox_array, node_shape) {
+                return Err(WfcError::ShapeMismatch { path: entry_path, expected: node_shape, found: node_shape_of(&vox_array) });
+            }
+            if assets.iter().any(|(_, other_name, _)| *other_name == asset_name) {
+                return Err(WfcError::DuplicateAsset { path: entry_path, name: asset_name });
+            }
+            assets.push((entry_path.clone(), asset_name, vox_array));
+        }
+    }
 
-pub fn node_dict_from_directory(asset_dir: &String, shape: [usize; 3], exclusions: &HashSet<(&str, &str)>) -> HashMap<usize, Node> {
+    Ok(assets)
+}
+
+/// Generates a `Node` for each allowed rotation of each asset and its mirrored variants, along with their sockets and valid neighbors.
+/// Assets larger than `node_shape` are split into one `Node` per cell, joined so they can only be placed together.
+pub fn node_dict_from_assets(assets: &[Asset], node_shape: [usize; 3], exclusions: &[Exclusion], face_matching: &FaceMatching) -> HashMap<usize, Node> {
     let mut ret = HashMap::<usize, Node>::new();
 
     let mut side_socket_map = HashMap::<Array2<u8>, String>::new();
@@ -17,155 +58,359 @@ pub fn node_dict_from_directory(asset_dir: &String, shape: [usize; 3], exclusion
     let mut socket_serial:usize = 0;
     let mut node_serial:usize = 0;
 
-    let paths = fs::read_dir(asset_dir).unwrap();
+    // The part each joined face of a multi-cell asset must be placed against, by node id and direction
+    let mut links = HashMap::<(usize, usize), usize>::new();
 
-    for path in paths {
-        let entry = path.unwrap();
-        let vox_array = vox_array_from_xraw(entry.path().to_str().unwrap());
-        let file_name = entry.file_name().into_string().unwrap();
-        let asset_name = &String::from(&file_name[..file_name.len()-5]);
+    let [width, height, depth] = node_shape;
 
-        // Create reference node
-        let face_ny = vox_array.index_axis(Axis(0), 0).to_owned();
-        let face_py = vox_array.index_axis(Axis(0), shape[0]-1).to_owned();
-        let face_nx = vox_array.index_axis(Axis(1), 0).to_owned();
-        let face_px = vox_array.index_axis(Axis(1), shape[1]-1).to_owned();
-        let face_nz = vox_array.index_axis(Axis(2), 0).to_owned();
-        let face_pz = vox_array.index_axis(Axis(2), shape[2]-1).to_owned();
+    // Quarter turns would swap the width and depth
+    let square = width == depth;
 
-        let mut ori_node = Node::new(0, asset_name);
+    for asset in assets {
+        let [cells_x, cells_y, cells_z] = cell_counts(&asset.voxels, node_shape);
+        let big = cells_x * cells_y * cells_z > 1;
 
-        register_side_face(&mut side_socket_map, mirrored_face(&face_nx), &mut socket_serial, &mut ori_node.sockets.nx);
-        register_side_face(&mut side_socket_map, face_px, &mut socket_serial, &mut ori_node.sockets.px);
-        register_side_face(&mut side_socket_map, face_nz, &mut socket_serial, &mut ori_node.sockets.nz);
-        register_side_face(&mut side_socket_map, mirrored_face(&face_pz), &mut socket_serial, &mut ori_node.sockets.pz);
+        // The offset of every cell in the asset as authored, transformed along with the voxels
+        let offsets = Array3::from_shape_fn((cells_y, cells_x, cells_z), |(y, x, z)| [x, y, z]);
 
-        register_vert_face(&mut vert_socket_map, face_py.clone(), &mut socket_serial, &mut ori_node.sockets.py);
-        register_vert_face(&mut vert_socket_map, face_ny.clone(), &mut socket_serial, &mut ori_node.sockets.ny);
+        let mut instances = Vec::<([usize; 3], Vec<Node>)>::new();
 
-        for rot in 1..4 {
-            let mut rot_node = Node::new(rot, asset_name);
-            rotate_side_sockets(&ori_node.sockets, &mut rot_node.sockets, rot);
-            rotate_vert_socket(&ori_node.sockets.py, &mut rot_node.sockets.py, rot);
-            rotate_vert_socket(&ori_node.sockets.ny, &mut rot_node.sockets.ny, rot);
+        let mut reflections = vec![Reflection::None];
+        for reflection in &asset.reflections {
+            if !reflections.contains(reflection) {
+                reflections.push(*reflection);
+            }
+        }
 
-            ret.insert(node_serial, rot_node);
-            node_serial += 1;
+        for reflection in reflections {
+            let reflected = (reflected_array(&face_matching.classify(&asset.voxels), reflection), reflected_array(&offsets, reflection));
+
+            for up in asset.rotations.ups() {
+                let (mut vox_array, mut offset_array) = reflected.clone();
+
+                for rot in 0..4 {
+                    if rot > 0 {
+                        vox_array = rotated_array_y(&vox_array);
+                        offset_array = rotated_array_y(&offset_array);
+                    }
+                    if !asset.rotations.allows(rot) || (rot % 2 == 1 && !square) {
+                        continue;
+                    }
+
+                    let oriented = tipped_array(&vox_array, up);
+                    let oriented_offsets = tipped_array(&offset_array, up);
+                    let cells = node_shape_of(&oriented_offsets);
+
+                    let mut nodes = Vec::<Node>::new();
+                    for ((y, x, z), offset) in oriented_offsets.indexed_iter() {
+                        let mut node = Node::new(rot, &asset.name);
+                        node.tags = asset.tags.clone();
+                        node.reflection = reflection;
+                        node.orientation.up = *up;
+
+                        if big {
+                            node.part = Some(Part {
+                                offset: *offset,
+                                joined: [x + 1 < cells[0], x > 0, y + 1 < cells[1], y > 0, z + 1 < cells[2], z > 0],
+                            });
+                        }
+
+                        let cell = oriented.slice(s![y*height..(y+1)*height, x*width..(x+1)*width, z*depth..(z+1)*depth]).to_owned();
+                        register_faces(&cell, &mut side_socket_map, &mut vert_socket_map, &mut socket_serial, face_matching.allowed_differences(), &mut node.sockets);
+                        if let Some(sockets) = &asset.sockets {
+                            sockets.apply(&mut node);
+                        }
+                        nodes.push(node);
+                    }
+
+                    // Variants that look the same to their neighbors are merged into the first, which takes over their weight
+                    // so the asset is picked as often as if every variant had been kept
+                    let duplicate = instances.iter().position(|(other_cells, others)| {
+                        *other_cells == cells && others.iter().zip(&nodes).all(|(other, node)| other.sockets == node.sockets)
+                    });
+                    if let (true, Some(index)) = (asset.rotations.skips_duplicates(), duplicate) {
+                        for (other, node) in instances[index].1.iter_mut().zip(&nodes) {
+                            other.equivalents.push((node.reflection, node.orientation));
+                            other.weight += node.weight;
+                        }
+                        continue;
+                    }
+                    instances.push((cells, nodes));
+                }
+            }
         }
 
-        ret.insert(node_serial, ori_node);
-        node_serial += 1;
+        for (cells, nodes) in instances {
+            // Parts are in scan order over `[y, x, z]`, so neighboring parts are a fixed number of ids apart
+            let strides = [cells[2], cells[0] * cells[2], 1];
+
+            for node in nodes {
+                if let Some(part) = &node.part {
+                    for (dir, joined) in part.joined.iter().enumerate() {
+                        if !joined { continue; }
+                        let stride = strides[dir / 2];
+                        let partner = if dir & 1 == 0 { node_serial + stride } else { node_serial - stride };
+                        links.insert((node_serial, dir), partner);
+                    }
+                }
+
+                ret.insert(node_serial, node);
+                node_serial += 1;
+            }
+        }
     }
 
     let node_map_cpy = ret.clone();
 
     // Find valid neighbors
-    for (_, node) in &mut ret {
-        node.valid_neighbors.px.resize(node_map_cpy.len(), false);
-        node.valid_neighbors.nx.resize(node_map_cpy.len(), false);
-        node.valid_neighbors.pz.resize(node_map_cpy.len(), false);
-        node.valid_neighbors.nz.resize(node_map_cpy.len(), false);
-        node.valid_neighbors.py.resize(node_map_cpy.len(), false);
-        node.valid_neighbors.ny.resize(node_map_cpy.len(), false);
-        
+    for (id, node) in ret.iter_mut() {
+        let sockets = [&node.sockets.px, &node.sockets.nx, &node.sockets.py, &node.sockets.ny, &node.sockets.pz, &node.sockets.nz];
+        let mut valid_neighbors = [
+            &mut node.valid_neighbors.px,
+            &mut node.valid_neighbors.nx,
+            &mut node.valid_neighbors.py,
+            &mut node.valid_neighbors.ny,
+            &mut node.valid_neighbors.pz,
+            &mut node.valid_neighbors.nz,
+        ];
+        for neighbors in valid_neighbors.iter_mut() {
+            neighbors.resize(node_map_cpy.len(), false);
+        }
+
         for (other_id, other_node) in &node_map_cpy {
-            if exclusions.contains(&(node.asset_name.as_str(), other_node.asset_name.as_str())) {
-                continue;
+            let other_sockets = [&other_node.sockets.nx, &other_node.sockets.px, &other_node.sockets.ny, &other_node.sockets.py, &other_node.sockets.nz, &other_node.sockets.pz];
+            for dir in 0..6 {
+                // Joined faces only accept their own partner, overriding sockets and exclusions
+                let valid = match (links.get(&(*id, dir)), links.get(&(*other_id, dir ^ 1))) {
+                    (Some(partner), _) => partner == other_id,
+                    (None, Some(_)) => false,
+                    (None, None) => {
+                        socket_matches(sockets[dir], other_sockets[dir])
+                            && !exclusions.iter().any(|exclusion| exclusion.excludes(&node_map_cpy[id], other_node, dir))
+                    },
+                };
+                if valid { valid_neighbors[dir].set(*other_id, true); }
             }
-            if socket_matches(&node.sockets.px, &other_node.sockets.nx) { node.valid_neighbors.px.set(*other_id, true); }
-            if socket_matches(&node.sockets.nx, &other_node.sockets.px) { node.valid_neighbors.nx.set(*other_id, true); }
-            if socket_matches(&node.sockets.pz, &other_node.sockets.nz) { node.valid_neighbors.pz.set(*other_id, true); }
-            if socket_matches(&node.sockets.nz, &other_node.sockets.pz) { node.valid_neighbors.nz.set(*other_id, true); }
-            if socket_matches(&node.sockets.py, &other_node.sockets.ny) { node.valid_neighbors.py.set(*other_id, true); }
-            if socket_matches(&node.sockets.ny, &other_node.sockets.py) { node.valid_neighbors.ny.set(*other_id, true); }
         }
     }
 
     ret
 }
 
+/// The number of cells of `node_shape` an asset spans along `[x, y, z]`.
 #[inline]
-pub fn vox_array_from_xraw(path: &str) -> Array3<u8> {
-    let f = File::open(path)
-        .expect(&format!("could not open specified file {}", path));
+pub fn cell_counts(vox_array: &Array3<u8>, node_shape: [usize; 3]) -> [usize; 3] {
+    let shape = node_shape_of(vox_array);
+    [shape[0] / node_shape[0], shape[1] / node_shape[1], shape[2] / node_shape[2]]
+}
+
+/// Whether an asset covers a whole number of cells of `node_shape` along every axis.
+#[inline]
+pub fn fits_node_shape(vox_array: &Array3<u8>, node_shape: [usize; 3]) -> bool {
+    let shape = node_shape_of(vox_array);
+    (0..3).all(|i| node_shape[i] > 0 && shape[i] > 0 && shape[i].is_multiple_of(node_shape[i]))
+}
+
+/// The `[width, height, depth]` of an array indexed `[y, x, z]`.
+#[inline]
+pub fn node_shape_of<T>(vox_array: &Array3<T>) -> [usize; 3] {
+    let shape = vox_array.shape();
+    [shape[1], shape[0], shape[2]]
+}
+
+/// Loads every model in a voxel file, choosing the parser by file extension regardless of case.
+/// Files with an unknown extension are skipped. Models are named after the file stem,
+/// with the model index appended when a `.vox` file holds more than one model.
+fn vox_arrays_from_path(path: &Path) -> Result<Vec<(String, Array3<u8>)>, WfcError> {
+    let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) if !stem.is_empty() => stem,
+        _ => return Err(WfcError::BadFilename { path: path.to_path_buf() }),
+    };
+
+    match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()).as_deref() {
+        Some("xraw") => Ok(vec![(String::from(stem), vox_array_from_xraw(path)?)]),
+        Some("vox") => {
+            let models = vox_arrays_from_vox(path)?;
+            if models.len() == 1 {
+                return Ok(models.into_iter().map(|model| (String::from(stem), model)).collect());
+            }
+            Ok(models.into_iter()
+                .enumerate()
+                .map(|(i, model)| (format!("{}_{}", stem, i), model))
+                .collect())
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+#[inline]
+fn read_file(path: &Path) -> Result<Vec<u8>, WfcError> {
+    let io_error = |source| WfcError::Io { path: path.to_path_buf(), source };
+
+    let f = File::open(path).map_err(io_error)?;
 
     let mut reader = BufReader::new(f);
     let mut buffer = Vec::new();
 
-    reader.read_to_end(&mut buffer)
-        .expect(&format!("could not read specified file {}", path));
+    reader.read_to_end(&mut buffer).map_err(io_error)?;
 
-    let magic = str::from_utf8(&buffer[0..4]).unwrap();
-    assert_eq!(magic, "XRAW");
+    Ok(buffer)
+}
 
-    let bits_per_index = buffer[7];
-    assert_eq!(bits_per_index, 8);
+#[inline]
+fn read_bytes<'a>(buffer: &'a [u8], start: usize, len: usize, path: &Path) -> Result<&'a [u8], WfcError> {
+    start.checked_add(len)
+        .and_then(|end| buffer.get(start..end))
+        .ok_or_else(|| WfcError::TruncatedData { path: path.to_path_buf() })
+}
 
-    let width = usize_from_bits(&buffer[8..12]);
-    let height = usize_from_bits(&buffer[12..16]);
-    let depth = usize_from_bits(&buffer[16..20]);
+/// The largest size of a `.vox` model along any axis.
+const MAX_VOX_SIZE: usize = 256;
 
-    let data = &buffer[24..(24 + (width * height * depth) as usize)];
+#[inline]
+pub fn vox_array_from_xraw(path: &Path) -> Result<Array3<u8>, WfcError> {
+    let buffer = read_file(path)?;
+
+    if read_bytes(&buffer, 0, 4, path)? != b"XRAW" {
+        return Err(WfcError::BadMagic { path: path.to_path_buf(), expected: "XRAW" });
+    }
+
+    let bits_per_index = read_bytes(&buffer, 7, 1, path)?[0];
+    if bits_per_index != 8 {
+        return Err(WfcError::UnsupportedBitDepth { path: path.to_path_buf(), bits: bits_per_index });
+    }
 
-    Array3::from_shape_vec((width, height, depth), data.to_vec()).unwrap()
+    let width = usize_from_bits(read_bytes(&buffer, 8, 4, path)?);
+    let height = usize_from_bits(read_bytes(&buffer, 12, 4, path)?);
+    let depth = usize_from_bits(read_bytes(&buffer, 16, 4, path)?);
+
+    if width == 0 || height == 0 || depth == 0 {
+        return Err(WfcError::InvalidSize { path: path.to_path_buf(), size: [width, height, depth] });
+    }
+
+    // A size too large to count can't fit in the file either
+    let len = width.checked_mul(height)
+        .and_then(|len| len.checked_mul(depth))
+        .ok_or_else(|| WfcError::TruncatedData { path: path.to_path_buf() })?;
+    let data = read_bytes(&buffer, 24, len, path)?;
+
+    // Indices are stored with `x` changing fastest
+    Array3::from_shape_vec((depth, height, width), data.to_vec())
+        .map_err(|_| WfcError::TruncatedData { path: path.to_path_buf() })
+}
+
+/// Parses a native MagicaVoxel `.vox` file into one array per model.
+/// Arrays are indexed `[z, y, x]` in MagicaVoxel coordinates, matching the layout of `vox_array_from_xraw`.
+/// Voxels hold palette indices, so the `RGBA` palette and scene graph chunks are skipped.
+/// Models are limited to `MAX_VOX_SIZE` voxels along each axis, like in MagicaVoxel.
+/// Returns an error if the file ends partway through a chunk or holds no models.
+pub fn vox_arrays_from_vox(path: &Path) -> Result<Vec<Array3<u8>>, WfcError> {
+    let buffer = read_file(path)?;
+
+    if read_bytes(&buffer, 0, 4, path)? != b"VOX " {
+        return Err(WfcError::BadMagic { path: path.to_path_buf(), expected: "VOX" });
+    }
+
+    let mut models = Vec::new();
+    let mut size = None;
+
+    // Skip the header and the `MAIN` chunk header, its children follow directly
+    if read_bytes(&buffer, 8, 4, path)? != b"MAIN" {
+        return Err(WfcError::BadMagic { path: path.to_path_buf(), expected: "VOX" });
+    }
+
+    let mut offset = 20 + usize_from_bits(read_bytes(&buffer, 12, 4, path)?);
+
+    while offset < buffer.len() {
+        let header = read_bytes(&buffer, offset, 12, path)?;
+        let chunk_id = &header[0..4];
+        let content_size = usize_from_bits(&header[4..8]);
+        let children_size = usize_from_bits(&header[8..12]);
+        let content = read_bytes(&buffer, offset + 12, content_size, path)?;
+
+        match chunk_id {
+            b"SIZE" => {
+                let model_size = [
+                    usize_from_bits(read_bytes(content, 0, 4, path)?),
+                    usize_from_bits(read_bytes(content, 4, 4, path)?),
+                    usize_from_bits(read_bytes(content, 8, 4, path)?),
+                ];
+                if model_size.iter().any(|len| *len == 0 || *len > MAX_VOX_SIZE) {
+                    return Err(WfcError::InvalidSize { path: path.to_path_buf(), size: model_size });
+                }
+                size = Some(model_size);
+            },
+            b"XYZI" => {
+                let [size_x, size_y, size_z] = size.take()
+                    .ok_or_else(|| WfcError::TruncatedData { path: path.to_path_buf() })?;
+                let mut model = Array3::<u8>::zeros((size_z, size_y, size_x));
+
+                let num_voxels = usize_from_bits(read_bytes(content, 0, 4, path)?);
+                let len = num_voxels.checked_mul(4)
+                    .ok_or_else(|| WfcError::TruncatedData { path: path.to_path_buf() })?;
+                for voxel in read_bytes(content, 4, len, path)?.chunks_exact(4) {
+                    let (x, y, z) = (voxel[0] as usize, voxel[1] as usize, voxel[2] as usize);
+                    if let Some(cell) = model.get_mut([z, y, x]) {
+                        *cell = voxel[3];
+                    }
+                }
+
+                models.push(model);
+            },
+            _ => {}
+        }
+
+        offset = (offset + 12 + content_size).saturating_add(children_size);
+    }
+
+    if models.is_empty() {
+        return Err(WfcError::NoModels { path: path.to_path_buf() });
+    }
+
+    Ok(models)
 }
 
 #[inline]
-fn socket_matches (a: &String, b: &String) -> bool {
+fn socket_matches (a: &str, b: &str) -> bool {
     let a_last = a.chars().last().unwrap();
     let b_last = b.chars().last().unwrap();
 
     if (a_last == 'f' && b_last == 'm' || a_last == 'm' && b_last == 'f') && a[..a.len()-1] == b[..b.len()-1] {
         return true;
-    } else if a == b && a_last != 'f' && b_last != 'f' && a_last != 'm' && b_last != 'm' {
-        return true;
     }
 
-    false
+    a == b && a_last != 'f' && b_last != 'f' && a_last != 'm' && b_last != 'm'
 }
 
+/// Derives the sockets of every face of a voxel array, registering any faces that haven't been seen yet.
 #[inline]
-fn rotate_side_sockets(original: &Sockets, sockets: &mut Sockets, rotation: u8) {
-    match rotation {
-        1 => {
-            sockets.px = original.pz.clone();
-            sockets.nx = original.nz.clone();
-            sockets.pz = original.nx.clone();
-            sockets.nz = original.px.clone();
-        },
-        2 => {
-            sockets.px = original.nx.clone();
-            sockets.nx = original.px.clone();
-            sockets.pz = original.nz.clone();
-            sockets.nz = original.pz.clone();
-        },
-        3 => {
-            sockets.px = original.nz.clone();
-            sockets.nx = original.pz.clone();
-            sockets.pz = original.px.clone();
-            sockets.nz = original.nx.clone();
-        },
-        _ => panic!("{} is an invalid number of rotations", rotation)
-    }
+fn register_faces(vox_array: &Array3<u8>, side_socket_map: &mut HashMap::<Array2<u8>, String>, vert_socket_map: &mut HashMap::<Array2<u8>, String>, serial: &mut usize, tolerance: usize, sockets: &mut Sockets) {
+    let shape = vox_array.shape();
+
+    let face_ny = vox_array.index_axis(Axis(0), 0).to_owned();
+    let face_py = vox_array.index_axis(Axis(0), shape[0]-1).to_owned();
+    let face_nx = vox_array.index_axis(Axis(1), 0).to_owned();
+    let face_px = vox_array.index_axis(Axis(1), shape[1]-1).to_owned();
+    let face_nz = vox_array.index_axis(Axis(2), 0).to_owned();
+    let face_pz = vox_array.index_axis(Axis(2), shape[2]-1).to_owned();
+
+    register_side_face(side_socket_map, mirrored_face(&face_nx), serial, tolerance, &mut sockets.nx);
+    register_side_face(side_socket_map, face_px, serial, tolerance, &mut sockets.px);
+    register_side_face(side_socket_map, face_nz, serial, tolerance, &mut sockets.nz);
+    register_side_face(side_socket_map, mirrored_face(&face_pz), serial, tolerance, &mut sockets.pz);
+
+    register_vert_face(vert_socket_map, face_py, serial, tolerance, &mut sockets.py);
+    register_vert_face(vert_socket_map, face_ny, serial, tolerance, &mut sockets.ny);
 }
 
 #[inline]
-fn rotate_vert_socket(original: &String, socket: &mut String, rotation: u8) {
-    let original_rot = original.chars().last().unwrap();
-    *socket = original.clone();
-    if original_rot != 'i' {
-        let mut new_rot = original_rot.to_digit(10).unwrap() as u8;
-        new_rot += rotation;
-        if new_rot > 3 {
-            new_rot -= 4;
+fn register_vert_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Array2<u8>, serial: &mut usize, tolerance: usize, socket: &mut String) {
+    if !socket_map.contains_key(&face) {
+        if let Some(nearest) = nearest_socket(socket_map, &face, tolerance) {
+            *socket = nearest;
+            return;
         }
-        socket.replace_range(socket.len()-1..socket.len(), &new_rot.to_string());
-    }
-}
 
-#[inline]
-fn register_vert_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Array2<u8>, serial: &mut usize, socket: &mut String) {
-    if !socket_map.contains_key(&face) {
         let rot_0 = face.clone();
         let rot_1 = rotated_array_p90(&rot_0);
         let rot_2 = rotated_array_p90(&rot_1);
@@ -186,8 +431,13 @@ fn register_vert_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Arra
 }
 
 #[inline]
-fn register_side_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Array2<u8>, serial: &mut usize, socket: &mut String) {
+fn register_side_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Array2<u8>, serial: &mut usize, tolerance: usize, socket: &mut String) {
     if !socket_map.contains_key(&face) {
+        if let Some(nearest) = nearest_side_socket(socket_map, &face, tolerance) {
+            *socket = nearest;
+            return;
+        }
+
         let mirror = mirrored_face(&face);
 
         if face == mirror {
@@ -202,6 +452,59 @@ fn register_side_face(socket_map: &mut HashMap::<Array2<u8>, String>, face: Arra
     *socket = socket_map[&face].clone();
 }
 
+/// The socket of the registered face closest to `face`, if it differs by at most `tolerance` voxels.
+/// Ties go to the lowest socket so the result doesn't depend on the order of the map.
+#[inline]
+fn nearest_socket(socket_map: &HashMap::<Array2<u8>, String>, face: &Array2<u8>, tolerance: usize) -> Option<String> {
+    if tolerance == 0 { return None; }
+
+    socket_map.iter()
+        .filter(|(registered, _)| registered.shape() == face.shape())
+        .map(|(registered, socket)| (face_differences(registered, face), socket))
+        .filter(|(differences, _)| *differences <= tolerance)
+        .min()
+        .map(|(_, socket)| socket.clone())
+}
+
+/// The side socket of the registered pair of faces closest to `face`, if it differs by at most `tolerance` voxels.
+/// A face and its mirror image always get the same symmetric socket or the two hands of a pair, so faces that
+/// would match exactly still match. Symmetric faces only take symmetric sockets, since they'd otherwise
+/// get the same hand on both sides. Ties go to the lowest serial so the result doesn't depend on the order of the map.
+#[inline]
+fn nearest_side_socket(socket_map: &HashMap::<Array2<u8>, String>, face: &Array2<u8>, tolerance: usize) -> Option<String> {
+    if tolerance == 0 { return None; }
+
+    let mirror = mirrored_face(face);
+    let symmetric = *face == mirror;
+
+    let (_, serial, hand, registered) = socket_map.iter()
+        .filter(|(registered, _)| registered.shape() == face.shape())
+        .filter_map(|(registered, socket)| {
+            let (serial, hand) = socket.split_at(socket.len() - 1);
+            if symmetric && hand != "s" { return None; }
+            Some((face_differences(registered, face), serial.parse::<usize>().ok()?, hand, registered))
+        })
+        .filter(|(differences, ..)| *differences <= tolerance)
+        .min_by_key(|(differences, serial, ..)| (*differences, *serial))?;
+
+    if hand == "s" {
+        return Some(format!("{}s", serial));
+    }
+
+    // Take the hand of whichever face of the pair is closer, falling back to an order that flips for the mirror image
+    let (face_m, face_f) = if hand == "m" { (registered.clone(), mirrored_face(registered)) } else { (mirrored_face(registered), registered.clone()) };
+    let (to_m, to_f) = (face_differences(&face_m, face), face_differences(&face_f, face));
+    let is_m = to_m < to_f || to_m == to_f && face.iter().lt(mirror.iter());
+
+    Some(format!("{}{}", serial, if is_m { "m" } else { "f" }))
+}
+
+/// The number of voxels two faces of the same shape differ by.
+#[inline]
+fn face_differences(a: &Array2<u8>, b: &Array2<u8>) -> usize {
+    a.iter().zip(b.iter()).filter(|(a, b)| a != b).count()
+}
+
 #[inline]
 fn rotated_array_p90(arr: &Array2<u8>) -> Array2<u8> {
     let mut ret = arr.clone();
@@ -211,9 +514,52 @@ fn rotated_array_p90(arr: &Array2<u8>) -> Array2<u8> {
     ret
 }
 
+/// Rotates a voxel array a quarter turn about the `Y` axis, so the `+Z` face becomes the `+X` face.
+#[inline]
+fn rotated_array_y<T: Clone>(vox_array: &Array3<T>) -> Array3<T> {
+    let mut ret = vox_array.view();
+    ret.swap_axes(1, 2);
+    ret.slice(s![.., .., ..;-1]).to_owned()
+}
+
+/// Tips an array so its `+Y` axis points `up`.
+#[inline]
+fn tipped_array<T: Clone>(vox_array: &Array3<T>, up: &Direction) -> Array3<T> {
+    // Quarter turns about the `X` axis taking `+Y` to `+Z`, and about the `Z` axis taking `+Y` to `+X`
+    let turned_x = |arr: &Array3<T>| {
+        let mut ret = arr.view().permuted_axes([2, 1, 0]);
+        ret.invert_axis(Axis(0));
+        ret.to_owned()
+    };
+    let turned_z = |arr: &Array3<T>| {
+        let mut ret = arr.view().permuted_axes([1, 0, 2]);
+        ret.invert_axis(Axis(0));
+        ret.to_owned()
+    };
+
+    match up {
+        Direction::POSY => vox_array.clone(),
+        Direction::POSZ => turned_x(vox_array),
+        Direction::NEGY => turned_x(&turned_x(vox_array)),
+        Direction::NEGZ => turned_x(&turned_x(&turned_x(vox_array))),
+        Direction::POSX => turned_z(vox_array),
+        Direction::NEGX => turned_z(&turned_z(&turned_z(vox_array))),
+    }
+}
+
+/// Mirrors a voxel array across the given axis.
+#[inline]
+fn reflected_array<T: Clone>(vox_array: &Array3<T>, reflection: Reflection) -> Array3<T> {
+    match reflection {
+        Reflection::None => vox_array.clone(),
+        Reflection::X => vox_array.slice(s![.., ..;-1, ..]).to_owned(),
+        Reflection::Z => vox_array.slice(s![.., .., ..;-1]).to_owned(),
+    }
+}
+
 #[inline]
 fn usize_from_bits(array: &[u8]) -> usize {
-    ((array[0] as usize) <<  0) +
+    (array[0] as usize) +
     ((array[1] as usize) <<  8) +
     ((array[2] as usize) << 16) +
     ((array[3] as usize) << 24)
@@ -223,3 +569,358 @@ fn usize_from_bits(array: &[u8]) -> usize {
 fn mirrored_face(face: &Array2<u8>) -> Array2<u8> {
     face.slice(s![0..face.shape()[0]; 1, ..; -1]).to_owned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adjacency::dir_index;
+    use crate::node::{world_direction, Orientation};
+    use crate::wfc::DIRECTIONS;
+    use crate::{NodeSet, NodeSetBuilder};
+
+    /// Writes `buffer` to a file of its own in the temporary directory, loads it with `load`, and removes it again.
+    fn load_buffer<T>(name: &str, buffer: &[u8], load: fn(&Path) -> Result<T, WfcError>) -> Result<T, WfcError> {
+        let path = std::env::temp_dir().join(format!("wfc_voxel_{}_{}", std::process::id(), name));
+        fs::write(&path, buffer).unwrap();
+        let ret = load(&path);
+        fs::remove_file(&path).unwrap();
+        ret
+    }
+
+    fn xraw_header(size: [u32; 3]) -> Vec<u8> {
+        let mut buffer = b"XRAW".to_vec();
+        buffer.extend([0, 4, 8, 8]);
+        for len in size {
+            buffer.extend(len.to_le_bytes());
+        }
+        buffer.extend(256u32.to_le_bytes());
+        buffer
+    }
+
+    fn vox_chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((content.len() as u32).to_le_bytes());
+        chunk.extend((children.len() as u32).to_le_bytes());
+        chunk.extend(content);
+        chunk.extend(children);
+        chunk
+    }
+
+    fn vox_file(children: &[u8]) -> Vec<u8> {
+        let mut buffer = b"VOX ".to_vec();
+        buffer.extend(150u32.to_le_bytes());
+        buffer.extend(vox_chunk(b"MAIN", &[], children));
+        buffer
+    }
+
+    fn vox_size(size: [u32; 3]) -> Vec<u8> {
+        vox_chunk(b"SIZE", &size.iter().flat_map(|len| len.to_le_bytes()).collect::<Vec<u8>>(), &[])
+    }
+
+    #[test]
+    fn xraw_loads_in_z_y_x_order() {
+        let mut buffer = xraw_header([3, 2, 1]);
+        buffer.extend([1, 2, 3, 4, 5, 6]);
+
+        let vox_array = load_buffer("order.xraw", &buffer, vox_array_from_xraw).unwrap();
+        assert_eq!(vox_array.shape(), &[1, 2, 3]);
+        assert_eq!(vox_array[[0, 1, 0]], 4);
+    }
+
+    #[test]
+    fn xraw_with_overflowing_size_is_an_error() {
+        let buffer = xraw_header([u32::MAX; 3]);
+        let result = load_buffer("overflow.xraw", &buffer, vox_array_from_xraw);
+        assert!(matches!(result, Err(WfcError::TruncatedData { .. })));
+    }
+
+    #[test]
+    fn xraw_with_empty_size_is_an_error() {
+        let buffer = xraw_header([0, 4, 4]);
+        let result = load_buffer("empty.xraw", &buffer, vox_array_from_xraw);
+        assert!(matches!(result, Err(WfcError::InvalidSize { size: [0, 4, 4], .. })));
+    }
+
+    #[test]
+    fn xraw_with_missing_data_is_an_error() {
+        let mut buffer = xraw_header([2, 2, 2]);
+        buffer.extend([1; 7]);
+        let result = load_buffer("short.xraw", &buffer, vox_array_from_xraw);
+        assert!(matches!(result, Err(WfcError::TruncatedData { .. })));
+    }
+
+    #[test]
+    fn xraw_with_other_bit_depth_is_an_error() {
+        let mut buffer = xraw_header([1, 1, 1]);
+        buffer[7] = 16;
+        buffer.extend([1, 0]);
+        let result = load_buffer("depth.xraw", &buffer, vox_array_from_xraw);
+        assert!(matches!(result, Err(WfcError::UnsupportedBitDepth { bits: 16, .. })));
+    }
+
+    fn vox_voxels(voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut content = (voxels.len() as u32).to_le_bytes().to_vec();
+        content.extend(voxels.iter().flatten());
+        vox_chunk(b"XYZI", &content, &[])
+    }
+
+    #[test]
+    fn vox_loads_every_model() {
+        let mut children = vox_size([2, 3, 4]);
+        children.extend(vox_voxels(&[[0, 0, 0, 7], [1, 2, 3, 9], [5, 5, 5, 1]]));
+        children.extend(vox_size([1, 1, 1]));
+        children.extend(vox_voxels(&[[0, 0, 0, 3]]));
+        children.extend(vox_chunk(b"RGBA", &[0; 1024], &[]));
+
+        let models = load_buffer("models.vox", &vox_file(&children), vox_arrays_from_vox).unwrap();
+        assert_eq!(models.len(), 2);
+
+        assert_eq!(models[0].shape(), &[4, 3, 2]);
+        assert_eq!(models[0][[0, 0, 0]], 7);
+        assert_eq!(models[0][[3, 2, 1]], 9);
+        assert_eq!(models[0].iter().filter(|voxel| **voxel != 0).count(), 2);
+
+        assert_eq!(models[1].shape(), &[1, 1, 1]);
+        assert_eq!(models[1][[0, 0, 0]], 3);
+    }
+
+    #[test]
+    fn vox_with_oversized_model_is_an_error() {
+        for size in [[u32::MAX; 3], [257, 1, 1], [4, 0, 4]] {
+            let buffer = vox_file(&vox_size(size));
+            let result = load_buffer("oversized.vox", &buffer, vox_arrays_from_vox);
+            assert!(matches!(result, Err(WfcError::InvalidSize { .. })), "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn vox_with_bad_magic_is_an_error() {
+        let mut buffer = vox_file(&[]);
+        buffer[0] = b'X';
+        let result = load_buffer("magic.vox", &buffer, vox_arrays_from_vox);
+        assert!(matches!(result, Err(WfcError::BadMagic { .. })));
+    }
+
+    #[test]
+    fn vox_with_truncated_voxels_is_an_error() {
+        let mut voxels = 1000u32.to_le_bytes().to_vec();
+        voxels.extend([0, 0, 0, 1]);
+
+        let mut children = vox_size([2, 2, 2]);
+        children.extend(vox_chunk(b"XYZI", &voxels, &[]));
+
+        let result = load_buffer("truncated.vox", &vox_file(&children), vox_arrays_from_vox);
+        assert!(matches!(result, Err(WfcError::TruncatedData { .. })));
+    }
+
+    #[test]
+    fn vox_cut_off_in_a_chunk_header_is_an_error() {
+        let mut children = vox_size([1, 1, 1]);
+        children.extend(vox_voxels(&[[0, 0, 0, 1]]));
+
+        let mut buffer = vox_file(&children);
+        buffer.extend(b"RGBA");
+        let result = load_buffer("cut.vox", &buffer, vox_arrays_from_vox);
+        assert!(matches!(result, Err(WfcError::TruncatedData { .. })));
+    }
+
+    #[test]
+    fn vox_without_models_is_an_error() {
+        let buffer = vox_file(&vox_chunk(b"RGBA", &[0; 1024], &[]));
+        let result = load_buffer("no_models.vox", &buffer, vox_arrays_from_vox);
+        assert!(matches!(result, Err(WfcError::NoModels { .. })));
+    }
+
+    /// Writes each `(name, buffer)` to a directory of its own in the temporary directory, loads it, and removes it again.
+    fn load_directory(name: &str, files: &[(&str, Vec<u8>)]) -> Result<Vec<String>, WfcError> {
+        let dir = std::env::temp_dir().join(format!("wfc_voxel_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, buffer) in files {
+            fs::write(dir.join(file_name), buffer).unwrap();
+        }
+        let ret = assets_from_directory(&dir, [1, 1, 1]);
+        fs::remove_dir_all(&dir).unwrap();
+        ret.map(|assets| assets.into_iter().map(|(_, asset_name, _)| asset_name).collect())
+    }
+
+    fn single_voxel_files() -> (Vec<u8>, Vec<u8>) {
+        let mut xraw = xraw_header([1, 1, 1]);
+        xraw.push(1);
+
+        let mut children = vox_size([1, 1, 1]);
+        children.extend(vox_voxels(&[[0, 0, 0, 1]]));
+
+        (xraw, vox_file(&children))
+    }
+
+    #[test]
+    fn extensions_match_regardless_of_case() {
+        let (xraw, vox) = single_voxel_files();
+        let files = [("upper.XRAW", xraw), ("mixed.Vox", vox), ("notes.txt", b"not voxels".to_vec())];
+
+        let asset_names = load_directory("case", &files).unwrap();
+        assert_eq!(asset_names, ["mixed", "upper"]);
+    }
+
+    #[test]
+    fn model_names_taken_by_files_are_an_error() {
+        let (xraw, vox) = single_voxel_files();
+        let mut children = vox_size([1, 1, 1]);
+        children.extend(vox_voxels(&[[0, 0, 0, 1]]));
+        children.extend(vox_size([1, 1, 1]));
+        children.extend(vox_voxels(&[[0, 0, 0, 2]]));
+
+        let files = [("tile.vox", vox_file(&children)), ("tile_1.xraw", xraw), ("other.vox", vox)];
+        let result = load_directory("duplicate", &files);
+        assert!(matches!(result, Err(WfcError::DuplicateAsset { name, .. }) if name == "tile_1"));
+    }
+
+    /// The face of an array indexed `[y, x, z]` in direction `dir`.
+    fn face_of<T: Clone>(arr: &Array3<T>, dir: &Direction) -> Vec<T> {
+        let (axis, last) = match dir {
+            Direction::POSX => (1, true),
+            Direction::NEGX => (1, false),
+            Direction::POSY => (0, true),
+            Direction::NEGY => (0, false),
+            Direction::POSZ => (2, true),
+            Direction::NEGZ => (2, false),
+        };
+        let index = if last { arr.shape()[axis] - 1 } else { 0 };
+        arr.index_axis(Axis(axis), index).iter().cloned().collect()
+    }
+
+    #[test]
+    fn orientations_agree_with_world_directions() {
+        // Every voxel holds its own position as `[x, y, z]`, doubled and centered so turns are exact
+        let positions = Array3::from_shape_fn((3, 3, 3), |(y, x, z)| [2 * x as i8 - 2, 2 * y as i8 - 2, 2 * z as i8 - 2]);
+
+        for reflection in [Reflection::None, Reflection::X, Reflection::Z] {
+            for up in Rotations::Cube.ups() {
+                let mut turned = reflected_array(&positions, reflection);
+                for turns in 0..4 {
+                    if turns > 0 {
+                        turned = rotated_array_y(&turned);
+                    }
+                    let orientation = Orientation { up: *up, turns };
+                    let oriented = tipped_array(&turned, up);
+
+                    let matrix = orientation.matrix();
+                    for ((y, x, z), position) in oriented.indexed_iter() {
+                        let mut authored = *position;
+                        match reflection {
+                            Reflection::None => {},
+                            Reflection::X => authored[0] = -authored[0],
+                            Reflection::Z => authored[2] = -authored[2],
+                        }
+                        let expected: [i8; 3] = std::array::from_fn(|i| (0..3).map(|k| matrix[i][k] * authored[k]).sum());
+                        assert_eq!(expected, [2 * x as i8 - 2, 2 * y as i8 - 2, 2 * z as i8 - 2], "{:?} {:?}", reflection, orientation);
+                    }
+
+                    for dir in DIRECTIONS {
+                        let world_dir = world_direction(reflection, &orientation, dir);
+                        let mut expected = face_of(&positions, dir);
+                        let mut found = face_of(&oriented, &world_dir);
+                        expected.sort();
+                        found.sort();
+                        assert_eq!(expected, found, "{:?} {:?} {:?}", reflection, orientation, dir);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn elbow_has_twelve_orientations() {
+        let mut elbow = Array3::<u8>::zeros((3, 3, 3));
+        elbow.slice_mut(s![1, 1.., 1]).fill(1);
+        elbow.slice_mut(s![1.., 1, 1]).fill(1);
+
+        let node_set = NodeSetBuilder::new(3)
+            .asset("elbow", elbow)
+            .rotations("elbow", Rotations::Cube)
+            .build()
+            .unwrap();
+
+        let mut arms = node_set.node_dict().values()
+            .map(|node| [dir_index(&node.world_direction(&Direction::POSX)), dir_index(&node.world_direction(&Direction::POSY))])
+            .map(|mut arms| { arms.sort(); arms })
+            .collect::<Vec<[usize; 2]>>();
+        arms.sort();
+        arms.dedup();
+
+        assert_eq!(node_set.node_dict().len(), 12);
+        assert_eq!(arms.len(), 12);
+        assert!(node_set.node_dict().values().all(|node| node.poses().count() == 2));
+    }
+
+    fn solid_cube() -> Array3<u8> {
+        Array3::from_elem((3, 3, 3), 1)
+    }
+
+    /// A cube missing one voxel on an edge of each side face, so no side face is its own mirror image.
+    fn chipped_cube() -> Array3<u8> {
+        let mut ret = solid_cube();
+        ret[[1, 0, 0]] = 0;
+        ret[[1, 2, 2]] = 0;
+        ret
+    }
+
+    fn connects_to_itself(node_set: &NodeSet, asset: &str) -> bool {
+        let bits = node_set.asset_bits(&asset.to_string()).unwrap();
+        bits.iter_ones().all(|id| {
+            let neighbors = &node_set.node_dict()[&id].valid_neighbors;
+            [&neighbors.px, &neighbors.nx, &neighbors.py, &neighbors.ny, &neighbors.pz, &neighbors.nz].iter().all(|dir| dir[id])
+        })
+    }
+
+    #[test]
+    fn identical_faces_connect_under_tolerance() {
+        for face_matching in [FaceMatching::exact(), FaceMatching::exact().tolerance(1), FaceMatching::occupancy().tolerance(2)] {
+            let node_set = NodeSetBuilder::new(3)
+                .asset("chipped", chipped_cube())
+                .asset("solid", solid_cube())
+                .face_matching(face_matching)
+                .build()
+                .unwrap();
+
+            assert!(connects_to_itself(&node_set, "solid"));
+        }
+    }
+
+    #[test]
+    fn symmetric_faces_only_take_symmetric_sockets() {
+        let node_set = NodeSetBuilder::new(3)
+            .asset("chipped", chipped_cube())
+            .asset("solid", solid_cube())
+            .face_matching(FaceMatching::exact().tolerance(1))
+            .build()
+            .unwrap();
+
+        let solid = node_set.asset_bits(&"solid".to_string()).unwrap().first_one().unwrap();
+        let sockets = &node_set.node_dict()[&solid].sockets;
+        for socket in [&sockets.px, &sockets.nx, &sockets.pz, &sockets.nz] {
+            assert!(socket.ends_with('s'), "{}", socket);
+        }
+    }
+
+    #[test]
+    fn mirror_images_take_paired_sockets_under_tolerance() {
+        let mut face = Array2::<u8>::zeros((3, 3));
+        face[[0, 0]] = 1;
+
+        // As close to the registered face as to its mirror image
+        let mut near = face.clone();
+        near[[0, 2]] = 1;
+        near[[1, 0]] = 1;
+
+        let mut socket_map = HashMap::new();
+        let mut serial = 0;
+        let (mut registered, mut socket, mut mirror_socket) = (String::new(), String::new(), String::new());
+        register_side_face(&mut socket_map, face, &mut serial, 2, &mut registered);
+        register_side_face(&mut socket_map, mirrored_face(&near), &mut serial, 2, &mut mirror_socket);
+        register_side_face(&mut socket_map, near, &mut serial, 2, &mut socket);
+
+        assert_eq!(serial, 1);
+        assert!(socket_matches(&socket, &mirror_socket), "{} {}", socket, mirror_socket);
+    }
+}
@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use ndarray::Array3;
+use bitvec::prelude::*;
+
+/// Running totals over the options of a cell, updated whenever an option is removed.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct CellEntropy {
+    count: usize,
+    sum_weights: f64,
+    sum_weight_log_weights: f64,
+}
+
+/// The options of a cell before it was collapsed, so the collapse can be undone.
+#[derive(Clone)]
+pub struct CellState {
+    options: BitVec,
+    entropy: CellEntropy,
+}
+
+/// A cell queued for collapse. Entries go stale once the cell loses an option and are skipped when popped.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Entry {
+    entropy: f64,
+    pos: [usize; 3],
+    count: usize,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // Reversed so the `BinaryHeap` pops the lowest entropy first, then the first position in scan order
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.entropy.total_cmp(&self.entropy)
+            .then_with(|| other.pos.cmp(&self.pos))
+    }
+}
+
+/// The options of every cell in the map, along with incrementally tracked entropy.
+/// When the support count propagator is used, it also holds how many options of each neighbor support every option.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wave {
+    data: Array3<BitVec>,
+    entropy: Array3<CellEntropy>,
+    heap: BinaryHeap<Entry>,
+    uncollapsed: usize,
+    empty: usize,
+    supports: Vec<u32>,
+}
+
+impl Wave {
+    pub fn new(shape: [usize; 3], init_val: &BitVec, weights: &[f64], weight_log_weights: &[f64]) -> Self {
+        let cell = CellEntropy {
+            count: init_val.count_ones(),
+            sum_weights: init_val.iter_ones().map(|id| weights[id]).sum(),
+            sum_weight_log_weights: init_val.iter_ones().map(|id| weight_log_weights[id]).sum(),
+        };
+        let uncollapsed = if cell.count > 1 { shape.iter().product() } else { 0 };
+        let empty = if cell.count == 0 { shape.iter().product() } else { 0 };
+
+        Self {
+            data: Array3::from_elem(shape, init_val.clone()),
+            entropy: Array3::from_elem(shape, cell),
+            heap: BinaryHeap::new(),
+            uncollapsed,
+            empty,
+            supports: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn data(&self) -> &Array3<BitVec> {
+        &self.data
+    }
+
+    #[inline]
+    pub fn options_at(&self, pos: &[usize; 3]) -> &BitVec {
+        &self.data[*pos]
+    }
+
+    /// The number of cells with more than one option left.
+    #[inline]
+    pub fn uncollapsed(&self) -> usize {
+        self.uncollapsed
+    }
+
+    /// The number of cells without any options left.
+    #[inline]
+    pub fn empty(&self) -> usize {
+        self.empty
+    }
+
+    /// The number of options left in a cell.
+    #[inline]
+    pub fn count(&self, pos: &[usize; 3]) -> usize {
+        self.entropy[*pos].count
+    }
+
+    /// The Shannon entropy of a cell, using the weights of its remaining options.
+    #[inline]
+    pub fn shannon_entropy(&self, pos: &[usize; 3]) -> f64 {
+        let cell = &self.entropy[*pos];
+        if cell.sum_weights <= 0.0 { return 0.0; }
+
+        cell.sum_weights.ln() - cell.sum_weight_log_weights / cell.sum_weights
+    }
+
+    /// Removes an option from a cell. Returns `false` if the option was already removed.
+    #[inline]
+    pub fn ban(&mut self, pos: &[usize; 3], id: usize, weight: f64, weight_log_weight: f64) -> bool {
+        if !self.data[*pos][id] { return false; }
+
+        self.data[*pos].set(id, false);
+
+        let cell = &mut self.entropy[*pos];
+        cell.count -= 1;
+        cell.sum_weights -= weight;
+        cell.sum_weight_log_weights -= weight_log_weight;
+
+        if cell.count == 1 {
+            self.uncollapsed -= 1;
+        } else if cell.count == 0 {
+            self.empty += 1;
+        }
+        true
+    }
+
+    /// Adds back an option removed by `ban`.
+    #[inline]
+    pub fn unban(&mut self, pos: &[usize; 3], id: usize, weight: f64, weight_log_weight: f64) {
+        self.data[*pos].set(id, true);
+
+        let cell = &mut self.entropy[*pos];
+        cell.count += 1;
+        cell.sum_weights += weight;
+        cell.sum_weight_log_weights += weight_log_weight;
+
+        if cell.count == 2 {
+            self.uncollapsed += 1;
+        } else if cell.count == 1 {
+            self.empty -= 1;
+        }
+    }
+
+    /// Removes every option from a cell except `id`. Returns the state of the cell beforehand.
+    #[inline]
+    pub fn collapse(&mut self, pos: &[usize; 3], id: usize, weight: f64, weight_log_weight: f64) -> CellState {
+        let state = CellState { options: self.data[*pos].clone(), entropy: self.entropy[*pos].clone() };
+        if state.entropy.count > 1 {
+            self.uncollapsed -= 1;
+        }
+
+        self.data[*pos].set_elements(0);
+        self.data[*pos].set(id, true);
+        self.entropy[*pos] = CellEntropy {
+            count: 1,
+            sum_weights: weight,
+            sum_weight_log_weights: weight_log_weight,
+        };
+        state
+    }
+
+    /// Puts a cell back in the state returned by `collapse`.
+    #[inline]
+    pub fn uncollapse(&mut self, pos: &[usize; 3], state: CellState) {
+        if state.entropy.count > 1 {
+            self.uncollapsed += 1;
+        }
+
+        self.data[*pos] = state.options;
+        self.entropy[*pos] = state.entropy;
+    }
+
+    /// Queues a cell for collapse with the given entropy. Any earlier entry for the cell becomes stale.
+    #[inline]
+    pub fn push(&mut self, pos: &[usize; 3], entropy: f64) {
+        let count = self.entropy[*pos].count;
+        if count < 2 { return; }
+
+        self.heap.push(Entry { entropy, pos: *pos, count });
+
+        // Drop stale entries once they outnumber the cells, keeping snapshots of the wave small
+        if self.heap.len() > 2 * self.data.len() {
+            let entropy = &self.entropy;
+            self.heap.retain(|entry| entry.count == entropy[entry.pos].count);
+        }
+    }
+
+    /// Pops the uncollapsed cell with the lowest entropy, skipping stale entries.
+    #[inline]
+    pub fn pop(&mut self) -> Option<[usize; 3]> {
+        while let Some(entry) = self.heap.pop() {
+            if entry.count == self.entropy[entry.pos].count {
+                return Some(entry.pos);
+            }
+        }
+        None
+    }
+
+    /// Replaces the support counts, laid out by cell in scan order, then option, then direction.
+    #[inline]
+    pub fn set_supports(&mut self, supports: Vec<u32>) {
+        self.supports = supports;
+    }
+
+    /// The number of options in the neighbor in direction `dir` that support option `id` in cell `pos`.
+    #[inline]
+    pub fn support(&self, pos: &[usize; 3], id: usize, dir: usize) -> u32 {
+        self.supports[self.support_index(pos, id, dir)]
+    }
+
+    /// Removes one support of option `id` in cell `pos` from the neighbor in direction `dir`.
+    /// Returns the number of supports left, or `None` if there were none to remove.
+    #[inline]
+    pub fn remove_support(&mut self, pos: &[usize; 3], id: usize, dir: usize) -> Option<u32> {
+        let index = self.support_index(pos, id, dir);
+        let support = &mut self.supports[index];
+
+        *support = support.checked_sub(1)?;
+        Some(*support)
+    }
+
+    /// Adds back a support removed by `remove_support`.
+    #[inline]
+    pub fn restore_support(&mut self, pos: &[usize; 3], id: usize, dir: usize) {
+        let index = self.support_index(pos, id, dir);
+        self.supports[index] += 1;
+    }
+
+    #[inline]
+    fn support_index(&self, pos: &[usize; 3], id: usize, dir: usize) -> usize {
+        let shape = self.data.shape();
+        let cell = (pos[0] * shape[1] + pos[1]) * shape[2] + pos[2];
+
+        (cell * self.data[*pos].len() + id) * 6 + dir
+    }
+
+    /// Removes every queued entry, used before requeuing all cells with new entropies.
+    #[inline]
+    pub fn clear_queue(&mut self) {
+        self.heap.clear();
+    }
+}
@@ -1,22 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
 use ndarray::Array3;
-use rand::{rngs::StdRng, thread_rng, Rng, RngCore, SeedableRng};
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
+// The generator behind `StdRng`, used directly as it can be serialized
+use rand_chacha::ChaCha12Rng;
 use bitvec::prelude::*;
 
-use super::{node::Node, NodeSet};
+use super::wave::{CellState, Wave};
+use super::{Adjacency, NodeSet, NoObserver, Observer};
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     POSX,
     NEGX,
+    #[default]
     POSY,
     NEGY,
     POSZ,
     NEGZ,
 }
 
-pub static DIRECTIONS: &'static [Direction] = &[
+pub static DIRECTIONS: &[Direction] = &[
     Direction::POSX,
     Direction::NEGX,
     Direction::POSY,
@@ -25,39 +31,137 @@ pub static DIRECTIONS: &'static [Direction] = &[
     Direction::NEGZ,
 ];
 
+impl Direction {
+
+    /// The direction pointing the other way.
+    #[inline]
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::POSX => Direction::NEGX,
+            Direction::NEGX => Direction::POSX,
+            Direction::POSY => Direction::NEGY,
+            Direction::NEGY => Direction::POSY,
+            Direction::POSZ => Direction::NEGZ,
+            Direction::NEGZ => Direction::POSZ,
+        }
+    }
+}
 
 static OUT_OF_BOUNDS: [usize; 3] = [usize::MAX, usize::MAX, usize::MAX];
 
+/// Upper bound of the random noise added to Shannon entropy to break ties between cells.
+const ENTROPY_NOISE: f64 = 1e-6;
+
+/// How the solver picks the next cell to collapse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Heuristic {
+    /// The cell with the fewest remaining options, ties broken by scan order. This is the default.
+    Count,
+    /// The cell with the lowest weighted Shannon entropy, ties broken randomly.
+    Shannon,
+    /// The first uncollapsed cell in scan order.
+    Scanline,
+    /// A random uncollapsed cell.
+    Random,
+}
+
+/// How the solver removes options that are no longer supported after a cell changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Propagator {
+    /// Recomputes the union of valid neighbors of a changed cell. This is the default.
+    Union,
+    /// Keeps a count of supporting neighbor options for every option in every direction, so removing
+    /// an option only updates the counts it contributes to. Uses memory proportional to cells × nodes × 6.
+    SupportCount,
+}
+
+/// A collapse the solver can undo when it leads to a contradiction.
+#[derive(Clone)]
+struct Decision {
+    /// The length of the trail of changes before the collapse, counted from the start of solving.
+    trail: usize,
+    pos: [usize; 3],
+    id: usize,
+}
+
+/// A single change to the map, recorded while backtracking is enabled so it can be undone.
+#[derive(Clone)]
+enum Change {
+    Ban { pos: [usize; 3], id: usize },
+    Collapse { pos: [usize; 3], state: CellState },
+    Support { pos: [usize; 3], id: usize, dir: usize },
+}
+
+/// A saved map state and random number generator state of a `Solver`, created by `Solver::snapshot`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    wave: Wave,
+    rng: ChaCha12Rng,
+    heuristic: Heuristic,
+    propagator: Propagator,
+}
+
+/// The outcome of a single `Solver::step`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Step {
+    /// The cell at `pos` was collapsed to `node_id` and the result propagated.
+    Collapsed { pos: [usize; 3], node_id: usize },
+    /// Collapsing a cell left the cell at `pos` without options, and the solver backtracked to an earlier state.
+    Backtracked { pos: [usize; 3] },
+    /// The cell at `pos` was left without options and the map can't be solved from its current state.
+    Contradiction { pos: [usize; 3] },
+    /// Every cell has been collapsed.
+    Finished,
+}
+
+/// A solved map along with how it was found by `Solver::solve_with_retries`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Solution {
+    /// The solved map of node ids.
+    pub map: Array3<usize>,
+    /// The attempt that succeeded, starting at 0 for the attempt using the solver's own seed.
+    pub attempt: usize,
+    /// The seed that produced the map.
+    pub seed: u64,
+}
+
 /// Solver for the Wave Function Collapse Algorithm.
+/// Events are reported to an `Observer`, which is a no-op unless one is supplied with `with_observer`.
 #[allow(dead_code)]
 #[derive(Clone)]
-pub struct Solver {
-    data: Array3<BitVec>,
+pub struct Solver<O: Observer = NoObserver> {
+    wave: Wave,
     ushape: [usize; 3],
     ishape: [i32; 3],
-    node_dict: HashMap<usize, Node>,
-    rng: StdRng,
+    adjacency: Arc<Adjacency>,
+    weights: Vec<f64>,
+    weight_log_weights: Vec<f64>,
+    heuristic: Heuristic,
+    propagator: Propagator,
+    removals: Vec<([usize; 3], usize)>,
+    rng: ChaCha12Rng,
     seed: u64,
+    max_backtrack_depth: usize,
+    decisions: VecDeque<Decision>,
+    trail: VecDeque<Change>,
+    trail_start: usize,
+    observer: O,
 }
 
-#[allow(dead_code)]
 impl Solver {
     
     /// Creates a new random `Solver` given the `shape` of the map you want to generate.
     /// `init_val` is the value each cell is initialized with. Use the bit mask from your `NodeData` if unsure.
     #[inline]
     pub fn new(shape: [usize; 3], init_val: &BitVec, node_set: &NodeSet) -> Self {
-        let ishape = shape.map(|e| e as i32);
         let mut thread_rng = thread_rng();
         let seed = thread_rng.next_u64();
-        Self {
-            data: Array3::from_elem(shape, init_val.clone()),
-            ushape: shape,
-            ishape,
-            node_dict: node_set.node_dict().clone(),
-            rng: StdRng::seed_from_u64(seed),
-            seed
-        }
+        Self::from_seed(shape, init_val, node_set, &seed)
     }
     
     /// Creates a new `Solver` given a `seed` as u64 and the `shape` of the map you want to generate.
@@ -65,16 +169,87 @@ impl Solver {
     #[inline]
     pub fn from_seed(shape: [usize; 3], init_val: &BitVec, node_set: &NodeSet, seed: &u64) -> Self {
         let ishape = shape.map(|e| e as i32);
-        Self {
-            data: Array3::from_elem(shape, init_val.clone()),
+        let weights = Self::weights_of(node_set);
+        let weight_log_weights = Self::weight_log_weights_of(node_set);
+        let mut ret = Self {
+            wave: Wave::new(shape, init_val, &weights, &weight_log_weights),
             ushape: shape,
             ishape,
-            node_dict: node_set.node_dict().clone(),
-            rng: StdRng::seed_from_u64(*seed),
-            seed: *seed
+            adjacency: node_set.adjacency().clone(),
+            weights,
+            weight_log_weights,
+            heuristic: Heuristic::Count,
+            propagator: Propagator::Union,
+            removals: Vec::new(),
+            rng: ChaCha12Rng::seed_from_u64(*seed),
+            seed: *seed,
+            max_backtrack_depth: 0,
+            decisions: VecDeque::new(),
+            trail: VecDeque::new(),
+            trail_start: 0,
+            observer: NoObserver,
+        };
+        ret.constrain_edges();
+        // A contradiction here is reported by the first `step`
+        let _ = ret.propagate_all();
+        ret.queue_all();
+        ret
+    }
+}
+
+#[allow(dead_code)]
+impl<O: Observer> Solver<O> {
+    
+    /// Replaces the `Observer` that is notified of solving events.
+    pub fn with_observer<P: Observer>(self, observer: P) -> Solver<P> {
+        Solver {
+            wave: self.wave,
+            ushape: self.ushape,
+            ishape: self.ishape,
+            adjacency: self.adjacency,
+            weights: self.weights,
+            weight_log_weights: self.weight_log_weights,
+            heuristic: self.heuristic,
+            propagator: self.propagator,
+            removals: self.removals,
+            rng: self.rng,
+            seed: self.seed,
+            max_backtrack_depth: self.max_backtrack_depth,
+            decisions: self.decisions,
+            trail: self.trail,
+            trail_start: self.trail_start,
+            observer,
         }
     }
     
+    /// Get the `Observer` that is notified of solving events.
+    #[inline]
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+    
+    /// Get the `Observer` that is notified of solving events as mutable.
+    #[inline]
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+    
+    #[inline]
+    fn weights_of(node_set: &NodeSet) -> Vec<f64> {
+        let mut weights = vec![0.0; node_set.node_dict().len()];
+        for (id, node) in node_set.node_dict() {
+            weights[*id] = node.weight;
+        }
+        weights
+    }
+    
+    #[inline]
+    fn weight_log_weights_of(node_set: &NodeSet) -> Vec<f64> {
+        Self::weights_of(node_set).iter()
+            .map(|weight| if *weight > 0.0 { weight * weight.ln() } else { 0.0 })
+            .collect()
+    }
+    
     /// Get the shape of the map.
     #[inline]
     pub fn shape(&self) -> &[usize; 3] {
@@ -90,38 +265,119 @@ impl Solver {
     /// Set the seed of the solver.
     #[inline]
     pub fn set_seed(&mut self, seed: &u64) {
-        self.rng = StdRng::seed_from_u64(*seed);
+        self.rng = ChaCha12Rng::seed_from_u64(*seed);
         self.seed = *seed;
     }
+    
+    /// Get the heuristic used to pick the next cell to collapse.
+    #[inline]
+    pub fn heuristic(&self) -> &Heuristic {
+        &self.heuristic
+    }
+    
+    /// Set the heuristic used to pick the next cell to collapse.
+    #[inline]
+    pub fn set_heuristic(&mut self, heuristic: &Heuristic) {
+        self.heuristic = *heuristic;
+        self.queue_all();
+    }
+    
+    /// Get the propagator used to remove unsupported options.
+    #[inline]
+    pub fn propagator(&self) -> &Propagator {
+        &self.propagator
+    }
+    
+    /// Set the propagator used to remove unsupported options.
+    /// Switching to `Propagator::SupportCount` counts the supports of the current map state and
+    /// removes any options that are already unsupported.
+    #[inline]
+    pub fn set_propagator(&mut self, propagator: &Propagator) {
+        if self.propagator == *propagator { return; }
 
+        self.propagator = *propagator;
+        self.clear_decisions();
+        match self.propagator {
+            Propagator::Union => self.wave.set_supports(Vec::new()),
+            // A contradiction here is reported by the next `step`
+            Propagator::SupportCount => { let _ = self.init_supports(); },
+        }
+    }
+    
+    /// Get the maximum number of collapse decisions the solver can backtrack through.
     #[inline]
-    fn options_at(&self, pos: &[usize; 3]) -> &BitVec {
-        &self.data[*pos]
+    pub fn max_backtrack_depth(&self) -> &usize {
+        &self.max_backtrack_depth
     }
+    
+    /// Set the maximum number of collapse decisions the solver can backtrack through.
+    /// When a contradiction is reached, the most recent collapse is undone and its choice banned before solving continues.
+    /// Changes to the map are recorded so only they are undone, using memory proportional to the changes since the oldest decision kept.
+    /// A `depth` of 0 disables backtracking, which is the default.
+    #[inline]
+    pub fn set_max_backtrack_depth(&mut self, depth: &usize) {
+        self.max_backtrack_depth = *depth;
+        while self.decisions.len() > self.max_backtrack_depth {
+            self.forget_oldest_decision();
+        }
+    }
+
+    /// Saves the current map state and random number generator state, e.g. to undo later constraints with `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            wave: self.wave.clone(),
+            rng: self.rng.clone(),
+            heuristic: self.heuristic,
+            propagator: self.propagator,
+        }
+    }
+    
+    /// Restores a map state and random number generator state saved by `snapshot`.
+    /// The snapshot must come from a `Solver` with the same shape and `NodeSet`.
+    /// Backtracking history is discarded, since it no longer leads to the restored state.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.wave = snapshot.wave.clone();
+        self.rng = snapshot.rng.clone();
+        self.removals.clear();
+        self.clear_decisions();
 
+        if snapshot.propagator != self.propagator {
+            match self.propagator {
+                Propagator::Union => self.wave.set_supports(Vec::new()),
+                // A contradiction here is reported by the next `step`
+                Propagator::SupportCount => { let _ = self.init_supports(); },
+            }
+        }
+        if snapshot.heuristic != self.heuristic {
+            self.queue_all();
+        }
+    }
+    
+    /// The nodes that can still be placed at a specific cell in the grid.
     #[inline]
-    fn options_at_mut(&mut self, pos: &[usize; 3]) -> &mut BitVec {
-        &mut self.data[*pos]
+    pub fn options_at(&self, pos: &[usize; 3]) -> &BitVec {
+        self.wave.options_at(pos)
     }
     
     /// Automatically solves the current map state.
     /// Returns the solved map if successful. Returns `None` if not.
+    /// Solving stops as soon as a contradiction is reached that can't be resolved by backtracking.
     pub fn solve(&mut self) -> Option<Array3<usize>> {
         let mut ret = Array3::zeros(self.ushape);
 
-        while !self.collapsed() {
-            self.iterate();
+        loop {
+            match self.step() {
+                Step::Finished => break,
+                // seed not solvable
+                Step::Contradiction { .. } => return None,
+                _ => {}
+            }
         }
 
         for x in 0..self.ushape[0] {
             for y in 0..self.ushape[1] {
                 for z in 0..self.ushape[2] {
-                    if self.options_at(&[x, y, z]).not_any() {
-                        // seed not solvable
-                        return None;
-                    } else {
-                        ret[[x, y, z]] = self.options_at(&[x, y, z]).first_one().unwrap();
-                    }
+                    ret[[x, y, z]] = self.options_at(&[x, y, z]).first_one().unwrap();
                 }
             }
         }
@@ -129,42 +385,208 @@ impl Solver {
         Some(ret)
     }
 
+    /// Automatically solves the current map state, restarting with a new seed whenever solving fails.
+    /// Every attempt starts from the map state at the time of calling, so earlier constraints are kept.
+    /// Seeds are derived deterministically from the solver's seed, with the first attempt using the seed itself.
+    /// Returns `None` and leaves the map state untouched if all `max_attempts` fail.
+    pub fn solve_with_retries(&mut self, max_attempts: &usize) -> Option<Solution> {
+        let initial = self.wave.clone();
+        let mut seeds = ChaCha12Rng::seed_from_u64(self.seed);
+
+        for attempt in 0..*max_attempts {
+            let seed = if attempt == 0 { self.seed } else { seeds.next_u64() };
+
+            self.wave = initial.clone();
+            self.clear_decisions();
+            self.rng = ChaCha12Rng::seed_from_u64(seed);
+
+            if let Some(map) = self.solve() {
+                return Some(Solution { map, attempt, seed });
+            }
+        }
+
+        self.wave = initial;
+        self.clear_decisions();
+        None
+    }
+
+    /// Collapses a single cell picked by the heuristic and propagates the result, allowing the map to be
+    /// inspected between steps with `options_at`. On a contradiction, the solver backtracks if enabled.
+    pub fn step(&mut self) -> Step {
+        if self.wave.empty() > 0 {
+            let pos = self.find_empty();
+            self.observer.on_contradiction(&pos);
+            return Step::Contradiction { pos };
+        }
+
+        let Some(pos) = self.get_min_entropy_pos() else {
+            self.observer.on_complete();
+            return Step::Finished;
+        };
+
+        if self.decisions.is_empty() {
+            self.clear_decisions();
+        } else if self.decisions.len() == self.max_backtrack_depth {
+            self.forget_oldest_decision();
+        }
+        let trail = self.trail_start + self.trail.len();
+
+        let id = self.collapse_at(&pos);
+        self.observer.on_collapse(&pos, id);
+
+        if self.max_backtrack_depth > 0 {
+            self.decisions.push_back(Decision { trail, pos, id });
+        }
+
+        match self.propagate(&pos) {
+            Ok(()) => Step::Collapsed { pos, node_id: id },
+            Err(pos) if self.backtrack() => {
+                self.observer.on_backtrack(&pos);
+                Step::Backtracked { pos }
+            },
+            Err(pos) => {
+                self.observer.on_contradiction(&pos);
+                Step::Contradiction { pos }
+            },
+        }
+    }
+
+    /// Whether every cell has been collapsed.
     #[inline]
-    fn collapsed(&self) -> bool {
+    pub fn collapsed(&self) -> bool {
+        self.wave.uncollapsed() == 0 && self.wave.empty() == 0
+    }
+
+    /// Finds a cell without any options.
+    fn find_empty(&self) -> [usize; 3] {
         for x in 0..self.ushape[0] {
             for y in 0..self.ushape[1] {
                 for z in 0..self.ushape[2] {
-                    if self.options_at(&[x, y, z]).count_ones() > 1 {
-                        return false;
+                    if self.options_at(&[x, y, z]).not_any() {
+                        return [x, y, z];
                     }
                 }
             }
         }
-        
-        true
+
+        OUT_OF_BOUNDS
     }
 
+    /// Undoes collapse decisions until banning the failed choice no longer leads to a contradiction.
+    /// Returns `false` if every recorded decision has been exhausted.
+    fn backtrack(&mut self) -> bool {
+        while let Some(decision) = self.decisions.pop_back() {
+            self.undo(decision.trail);
+            self.removals.clear();
+            self.constrain(&decision.pos, decision.id);
+            self.queue(&decision.pos);
+
+            if self.options_at(&decision.pos).any() && self.propagate(&decision.pos).is_ok() {
+                return true;
+            }
+        }
+
+        false
+    }
+    
+    /// Undoes every recorded change since the trail had length `trail`, then requeues the cells that changed.
+    fn undo(&mut self, trail: usize) {
+        let mut changed = HashSet::<[usize; 3]>::new();
+
+        while self.trail_start + self.trail.len() > trail {
+            let Some(change) = self.trail.pop_back() else { break; };
+            match change {
+                Change::Ban { pos, id } => {
+                    self.wave.unban(&pos, id, self.weights[id], self.weight_log_weights[id]);
+                    changed.insert(pos);
+                },
+                Change::Collapse { pos, state } => {
+                    self.wave.uncollapse(&pos, state);
+                    changed.insert(pos);
+                },
+                Change::Support { pos, id, dir } => self.wave.restore_support(&pos, id, dir),
+            }
+        }
+
+        for pos in changed {
+            self.queue(&pos);
+        }
+    }
+
+    /// Records a change to the map so it can be undone, if there is a decision to undo it for.
     #[inline]
-    fn iterate(&mut self) {
-        let pos = self.get_min_entropy_pos();
-        self.collapse_at(&pos);
-        self.propagate_from(&pos);
+    fn record(&mut self, change: Change) {
+        if self.max_backtrack_depth > 0 {
+            self.trail.push_back(change);
+        }
+    }
+
+    /// Drops the oldest decision along with the changes only it could undo.
+    fn forget_oldest_decision(&mut self) {
+        self.decisions.pop_front();
+
+        let end = self.decisions.front().map_or(self.trail_start + self.trail.len(), |decision| decision.trail);
+        self.trail.drain(..end - self.trail_start);
+        self.trail_start = end;
+    }
+
+    /// Drops every decision, used once earlier states no longer lead to the current one.
+    fn clear_decisions(&mut self) {
+        self.decisions.clear();
+        self.trail_start += self.trail.len();
+        self.trail.clear();
     }
     
-    fn collapse_at(&mut self, pos: &[usize; 3]) {
+    /// Collapses a cell to one of its options, picked with a probability proportional to its weight.
+    fn collapse_at(&mut self, pos: &[usize; 3]) -> usize {
         let options = self.options_at(pos).iter_ones().collect::<Vec<usize>>();
-        let to = options[self.rng.gen_range(0..options.len())];
+        let total_weight: f64 = options.iter().map(|id| self.weights[*id]).sum();
+
+        let to = if total_weight > 0.0 {
+            let mut remaining = self.rng.gen_range(0.0..total_weight);
+            let mut to = options[options.len() - 1];
+            for id in &options {
+                if remaining < self.weights[*id] {
+                    to = *id;
+                    break;
+                }
+                remaining -= self.weights[*id];
+            }
+            to
+        } else {
+            options[self.rng.gen_range(0..options.len())]
+        };
+
+        if self.propagator == Propagator::SupportCount {
+            self.removals.extend(options.iter().filter(|id| **id != to).map(|id| (*pos, *id)));
+        }
+        let state = self.wave.collapse(pos, to, self.weights[to], self.weight_log_weights[to]);
+        self.record(Change::Collapse { pos: *pos, state });
 
-        self.options_at_mut(pos).set_elements(0);
-        self.options_at_mut(pos).set(to, true);
+        to
     }
     
-    fn propagate_from(&mut self, pos: &[usize; 3]) {
+    /// Removes options that are no longer supported after `pos` changed, using the selected propagator.
+    /// Returns the position of the first cell left without any options.
+    #[inline]
+    fn propagate(&mut self, pos: &[usize; 3]) -> Result<(), [usize; 3]> {
+        match self.propagator {
+            Propagator::Union => self.propagate_from(pos),
+            Propagator::SupportCount => {
+                let ret = self.propagate_removals();
+                self.removals.clear();
+                ret
+            },
+        }
+    }
+
+    /// Removes options that are no longer supported by their neighbors, starting from `pos`.
+    /// Returns the position of the first cell left without any options.
+    fn propagate_from(&mut self, pos: &[usize; 3]) -> Result<(), [usize; 3]> {
         let mut pos_stack = vec![];
         pos_stack.push(*pos);
 
-        while !pos_stack.is_empty() {
-            let cur_pos = pos_stack.pop().unwrap();
+        while let Some(cur_pos) = pos_stack.pop() {
             for dir in DIRECTIONS {
                 let other_pos = self.add_dir_to_pos(&cur_pos, dir);
                 if other_pos == OUT_OF_BOUNDS { continue; }
@@ -183,8 +605,182 @@ impl Solver {
                         }
                     }
                 }
+
+                if pushed {
+                    if self.options_at(&other_pos).not_any() {
+                        return Err(other_pos);
+                    }
+                    self.queue(&other_pos);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes every option that isn't supported by its neighbors, like `init_supports` does for `Propagator::SupportCount`.
+    /// Returns the position of the first cell left without any options.
+    fn propagate_all(&mut self) -> Result<(), [usize; 3]> {
+        match self.propagator {
+            Propagator::Union => {
+                // Most cells share the same options, so the neighbors they allow are only found once per option set.
+                // Option sets are keyed by their raw words, as hashing a `BitVec` goes bit by bit
+                let mut allowed = HashMap::<Vec<usize>, Vec<BitVec>>::new();
+
+                let mut pos_stack = Vec::with_capacity(self.wave.data().len());
+                for x in (0..self.ushape[0]).rev() {
+                    for y in (0..self.ushape[1]).rev() {
+                        for z in (0..self.ushape[2]).rev() {
+                            pos_stack.push([x, y, z]);
+                        }
+                    }
+                }
+
+                while let Some(pos) = pos_stack.pop() {
+                    let options = self.options_at(&pos);
+                    if !allowed.contains_key(options.as_raw_slice()) {
+                        let valid_neighbors = DIRECTIONS.iter().map(|dir| self.valid_neighbors_of_set(options, dir)).collect();
+                        allowed.insert(options.as_raw_slice().to_vec(), valid_neighbors);
+                    }
+                    let valid_neighbors = &allowed[options.as_raw_slice()];
+
+                    for (dir, valid_neighbors) in DIRECTIONS.iter().zip(valid_neighbors.iter()) {
+                        let other_pos = self.add_dir_to_pos(&pos, dir);
+                        if other_pos == OUT_OF_BOUNDS { continue; }
+
+                        let other_options = self.options_at(&other_pos);
+                        let unsupported = other_options.as_raw_slice().iter()
+                            .zip(valid_neighbors.as_raw_slice())
+                            .any(|(options, valid)| options & !valid != 0);
+                        if !unsupported { continue; }
+
+                        let banned = other_options.iter_ones()
+                            .filter(|id| !valid_neighbors[*id])
+                            .collect::<Vec<usize>>();
+                        for id in banned {
+                            self.constrain(&other_pos, id);
+                        }
+
+                        if self.options_at(&other_pos).not_any() {
+                            return Err(other_pos);
+                        }
+                        pos_stack.push(other_pos);
+                    }
+                }
+
+                Ok(())
+            },
+            Propagator::SupportCount => self.init_supports(),
+        }
+    }
+
+    /// Removes the supports contributed by every removed option, banning options whose supports run out.
+    /// Returns the position of the first cell left without any options.
+    fn propagate_removals(&mut self) -> Result<(), [usize; 3]> {
+        let adjacency = self.adjacency.clone();
+
+        while let Some((pos, id)) = self.removals.pop() {
+            for (dir_index, dir) in DIRECTIONS.iter().enumerate() {
+                let other_pos = self.add_dir_to_pos(&pos, dir);
+                if other_pos == OUT_OF_BOUNDS { continue; }
+
+                let mut changed = false;
+
+                // Directions come in pairs, so the direction back to `pos` is the other half of the pair
+                for other_id in adjacency.neighbors(id, dir).iter_ones() {
+                    let Some(support) = self.wave.remove_support(&other_pos, other_id, dir_index ^ 1) else { continue; };
+                    self.record(Change::Support { pos: other_pos, id: other_id, dir: dir_index ^ 1 });
+
+                    if support == 0 && self.options_at(&other_pos)[other_id] {
+                        self.constrain(&other_pos, other_id);
+                        changed = true;
+                    }
+                }
+
+                if changed {
+                    if self.options_at(&other_pos).not_any() {
+                        return Err(other_pos);
+                    }
+                    self.queue(&other_pos);
+                }
             }
         }
+
+        Ok(())
+    }
+
+    /// Counts the supports of every option from the current map state, then bans options without support.
+    /// Returns the position of the first cell left without any options.
+    fn init_supports(&mut self) -> Result<(), [usize; 3]> {
+        let len = self.adjacency.len();
+        let mut supports = vec![0; self.wave.data().len() * len * 6];
+
+        // Most neighbors share the same options, so the supports they give are only counted once per option set
+        let mut given_supports = HashMap::<BitVec, Vec<u32>>::new();
+
+        let mut cell = 0;
+        for x in 0..self.ushape[0] {
+            for y in 0..self.ushape[1] {
+                for z in 0..self.ushape[2] {
+                    for (dir_index, dir) in DIRECTIONS.iter().enumerate() {
+                        let other_pos = self.add_dir_to_pos(&[x, y, z], dir);
+
+                        if other_pos == OUT_OF_BOUNDS {
+                            for id in 0..len {
+                                supports[(cell * len + id) * 6 + dir_index] = 1;
+                            }
+                            continue;
+                        }
+
+                        let other_options = self.options_at(&other_pos);
+                        let given = given_supports.entry(other_options.clone()).or_insert_with(|| {
+                            let mut given = vec![0; len * 6];
+                            for other_id in other_options.iter_ones() {
+                                for (back_index, back_dir) in DIRECTIONS.iter().enumerate() {
+                                    for id in self.adjacency.neighbors(other_id, back_dir).iter_ones() {
+                                        given[id * 6 + (back_index ^ 1)] += 1;
+                                    }
+                                }
+                            }
+                            given
+                        });
+
+                        for id in 0..len {
+                            supports[(cell * len + id) * 6 + dir_index] = given[id * 6 + dir_index];
+                        }
+                    }
+                    cell += 1;
+                }
+            }
+        }
+
+        self.wave.set_supports(supports);
+        self.removals.clear();
+
+        // Options without support in some direction are banned directly, their own removals are then propagated
+        for x in 0..self.ushape[0] {
+            for y in 0..self.ushape[1] {
+                for z in 0..self.ushape[2] {
+                    let pos = [x, y, z];
+                    let mut changed = false;
+
+                    for id in self.options_at(&pos).iter_ones().collect::<Vec<usize>>() {
+                        if (0..6).any(|dir_index| self.wave.support(&pos, id, dir_index) == 0) {
+                            self.constrain(&pos, id);
+                            changed = true;
+                        }
+                    }
+
+                    if changed {
+                        self.queue(&pos);
+                    }
+                }
+            }
+        }
+
+        let ret = self.propagate_removals();
+        self.removals.clear();
+        ret
     }
 
     #[inline]
@@ -210,67 +806,75 @@ impl Solver {
         ret.map(|e| e as usize)
     }
 
+    /// Pops the next cell to collapse from the queue of uncollapsed cells.
     #[inline]
-    fn get_min_entropy_pos(&self) -> [usize; 3] {
-        let mut min_entropy = usize::MAX;
-        let mut ret = [0, 0, 0];
+    fn get_min_entropy_pos(&mut self) -> Option<[usize; 3]> {
+        self.wave.pop()
+    }
 
-        for x in 0..self.ushape[0] {
-            for y in 0..self.ushape[1] {
-                for z in 0..self.ushape[2] {
+    /// Queues a cell for collapse using its current entropy under the selected heuristic.
+    #[inline]
+    fn queue(&mut self, pos: &[usize; 3]) {
+        let entropy = match self.heuristic {
+            Heuristic::Count => self.wave.count(pos) as f64,
+            Heuristic::Shannon => self.wave.shannon_entropy(pos) + self.rng.gen_range(0.0..ENTROPY_NOISE),
+            Heuristic::Scanline => 0.0,
+            Heuristic::Random => self.rng.gen(),
+        };
+
+        self.wave.push(pos, entropy);
+    }
 
-                    let cur_entropy = self.options_at(&[x, y, z]).count_ones();
+    /// Removes the parts of multi-cell assets from the edges of the map where the rest of the asset would be cut off.
+    fn constrain_edges(&mut self) {
+        for dir in DIRECTIONS {
+            let joined = self.adjacency.joined(dir).to_bitvec();
+            if joined.not_any() { continue; }
 
-                    if cur_entropy >= min_entropy { continue; }
-                    if cur_entropy < 2 { continue; }
+            let (axis, edge) = match dir {
+                Direction::POSX => (0, self.ushape[0].saturating_sub(1)),
+                Direction::NEGX => (0, 0),
+                Direction::POSY => (1, self.ushape[1].saturating_sub(1)),
+                Direction::NEGY => (1, 0),
+                Direction::POSZ => (2, self.ushape[2].saturating_sub(1)),
+                Direction::NEGZ => (2, 0),
+            };
 
-                    ret = [x, y, z];
-                    min_entropy = cur_entropy;
+            for x in 0..self.ushape[0] {
+                for y in 0..self.ushape[1] {
+                    for z in 0..self.ushape[2] {
+                        let pos = [x, y, z];
+                        if pos[axis] == edge {
+                            self.constrain_list(&pos, &joined);
+                        }
+                    }
                 }
             }
         }
-
-        ret
     }
 
-    #[inline]
-    fn valid_neighbors(&self, pos: &[usize; 3], dir: &Direction) -> BitVec {
-        let mut ret = BitVec::new();
-        ret.resize(self.node_dict.len(), false);
-
-        for id in self.options_at(pos).iter_ones() {
-            let node = &self.node_dict[&id];
-            
-            match dir {
-                Direction::POSX => { ret |= &node.valid_neighbors.px; }
-                Direction::NEGX => { ret |= &node.valid_neighbors.nx; }
-                Direction::POSY => { ret |= &node.valid_neighbors.py; }
-                Direction::NEGY => { ret |= &node.valid_neighbors.ny; }
-                Direction::POSZ => { ret |= &node.valid_neighbors.pz; }
-                Direction::NEGZ => { ret |= &node.valid_neighbors.nz; }
+    /// Requeues every uncollapsed cell, used when the heuristic changes.
+    fn queue_all(&mut self) {
+        self.wave.clear_queue();
+
+        for x in 0..self.ushape[0] {
+            for y in 0..self.ushape[1] {
+                for z in 0..self.ushape[2] {
+                    self.queue(&[x, y, z]);
+                }
             }
         }
+    }
 
-        ret
+    #[inline]
+    fn valid_neighbors(&self, pos: &[usize; 3], dir: &Direction) -> BitVec {
+        self.valid_neighbors_of_set(self.options_at(pos), dir)
     }
 
     #[inline]
     fn valid_neighbors_of_set(&self, node_ids: &BitVec, dir: &Direction) -> BitVec {
-        let mut ret = BitVec::new();
-        ret.resize(self.node_dict.len(), false);
-
-        for id in node_ids.iter_ones() {
-            let node = &self.node_dict[&id];
-            
-            match dir {
-                Direction::POSX => { ret |= &node.valid_neighbors.px; }
-                Direction::NEGX => { ret |= &node.valid_neighbors.nx; }
-                Direction::POSY => { ret |= &node.valid_neighbors.py; }
-                Direction::NEGY => { ret |= &node.valid_neighbors.ny; }
-                Direction::POSZ => { ret |= &node.valid_neighbors.pz; }
-                Direction::NEGZ => { ret |= &node.valid_neighbors.nz; }
-            }
-        }
+        let mut ret = BitVec::repeat(false, self.adjacency.len());
+        self.adjacency.union_into(node_ids, dir, &mut ret);
 
         ret
     }
@@ -278,14 +882,24 @@ impl Solver {
     /// Constrain the possible nodes at a specifc cell in the grid.
     pub fn constrain_list(&mut self, pos: &[usize; 3], bits: &BitVec) {
         for id in bits.iter_ones() {
-            self.options_at_mut(pos).set(id, false);
+            self.constrain(pos, id);
         }
-        self.propagate_from(pos);
+        self.queue(pos);
+
+        // Earlier states no longer hold this constraint, and a contradiction here is reported by the next `step`
+        self.clear_decisions();
+        let _ = self.propagate(pos);
     }
     
     #[inline]
     fn constrain(&mut self, pos: &[usize; 3], id: usize) {
-        self.options_at_mut(pos).set(id, false);
+        if !self.wave.ban(pos, id, self.weights[id], self.weight_log_weights[id]) { return; }
+
+        self.record(Change::Ban { pos: *pos, id });
+        self.observer.on_ban(pos, id);
+        if self.propagator == Propagator::SupportCount {
+            self.removals.push((*pos, id));
+        }
     }
     
     /// Constrain the possible nodes at a specifc cell in the grid based on a set of neighbours you want for a specific direction.
@@ -297,6 +911,241 @@ impl Solver {
                 self.constrain(pos, id);
             }
         }
-        self.propagate_from(pos);
+        self.queue(pos);
+
+        // Earlier states no longer hold this constraint, and a contradiction here is reported by the next `step`
+        self.clear_decisions();
+        let _ = self.propagate(pos);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use ndarray::{s, Array3};
+
+    use super::*;
+    use crate::adjacency::dir_index;
+    use crate::NodeSetBuilder;
+
+    /// A small tile set of ground, air and pillars, with some pairs that can't be placed next to each other.
+    fn node_set() -> NodeSet {
+        let mut ground = Array3::<u8>::zeros((4, 4, 4));
+        ground.slice_mut(s![..2, .., ..]).fill(1);
+
+        let mut pillar = Array3::<u8>::zeros((4, 4, 4));
+        pillar.slice_mut(s![.., 1..3, 1..3]).fill(2);
+
+        let mut ledge = ground.clone();
+        ledge.slice_mut(s![2.., ..2, ..]).fill(1);
+
+        NodeSetBuilder::new(4)
+            .asset("air", Array3::zeros((4, 4, 4)))
+            .asset("ground", ground)
+            .asset("pillar", pillar)
+            .asset("ledge", ledge)
+            .asset("block", Array3::from_elem((4, 4, 4), 1))
+            .build()
+            .unwrap()
+    }
+
+    /// The options of every cell, along with their supports when counted.
+    fn state(solver: &Solver) -> (Vec<BitVec>, Vec<u32>) {
+        let mut supports = Vec::new();
+        if solver.propagator == Propagator::SupportCount {
+            for (pos, options) in solver.wave.data().indexed_iter() {
+                for id in 0..options.len() {
+                    supports.extend((0..6).map(|dir| solver.wave.support(&[pos.0, pos.1, pos.2], id, dir)));
+                }
+            }
+        }
+        (solver.wave.data().iter().cloned().collect(), supports)
+    }
+
+    #[test]
+    fn propagators_solve_the_same_map() {
+        let node_set = node_set();
+
+        for seed in 0..10 {
+            for depth in [0, 8] {
+                let solve = |propagator: Propagator| {
+                    let mut solver = Solver::from_seed([6, 4, 6], node_set.bit_mask(), &node_set, &seed);
+                    solver.set_propagator(&propagator);
+                    solver.set_max_backtrack_depth(&depth);
+                    solver.solve()
+                };
+
+                assert_eq!(solve(Propagator::Union), solve(Propagator::SupportCount), "seed {} depth {}", seed, depth);
+            }
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn multi_cell_assets_are_placed_whole() {
+        // A rug two cells across with a mark near one corner, so each quarter turn is a different set of parts
+        let mut floor = Array3::<u8>::zeros((2, 2, 2));
+        floor.slice_mut(s![0, .., ..]).fill(1);
+        let mut rug = Array3::<u8>::zeros((2, 4, 4));
+        rug.slice_mut(s![0, .., ..]).fill(1);
+        rug[[1, 1, 1]] = 2;
+
+        let mut node_set = NodeSetBuilder::new(2)
+            .asset("floor", floor)
+            .asset("rug", rug)
+            .build()
+            .unwrap();
+        assert!(node_set.set_asset_weight(&"rug".to_string(), &20.0));
+        assert_eq!(node_set.asset_bits(&"rug".to_string()).unwrap().count_ones(), 16);
+
+        let shape = [5, 1, 5];
+        let mut parts = 0;
+        for seed in 0..20 {
+            let mut solver = Solver::from_seed(shape, node_set.bit_mask(), &node_set, &seed);
+            let Some(map) = solver.solve() else { continue; };
+
+            for ((x, y, z), id) in map.indexed_iter() {
+                let node = &node_set.node_dict()[id];
+                let Some(part) = node.part else { continue; };
+                parts += 1;
+
+                for dir in DIRECTIONS {
+                    // The part joined in `dir` of the asset as authored sits in the turned direction in the map
+                    let world_dir = node.world_direction(dir);
+                    let joined = part.joined[dir_index(&world_dir)];
+                    let step = |offset: usize, forward: bool| if forward { offset.checked_add(1) } else { offset.checked_sub(1) };
+                    let authored = match dir {
+                        Direction::POSX => step(part.offset[0], true).map(|x| [x, part.offset[1], part.offset[2]]),
+                        Direction::NEGX => step(part.offset[0], false).map(|x| [x, part.offset[1], part.offset[2]]),
+                        Direction::POSY => step(part.offset[1], true).map(|y| [part.offset[0], y, part.offset[2]]),
+                        Direction::NEGY => step(part.offset[1], false).map(|y| [part.offset[0], y, part.offset[2]]),
+                        Direction::POSZ => step(part.offset[2], true).map(|z| [part.offset[0], part.offset[1], z]),
+                        Direction::NEGZ => step(part.offset[2], false).map(|z| [part.offset[0], part.offset[1], z]),
+                    };
+                    assert_eq!(joined, authored.is_some_and(|offset| offset[0] < 2 && offset[1] < 1 && offset[2] < 2));
+                    if !joined { continue; }
+
+                    let neighbor = match world_dir {
+                        Direction::POSX => [x + 1, y, z],
+                        Direction::NEGX => [x.wrapping_sub(1), y, z],
+                        Direction::POSY => [x, y + 1, z],
+                        Direction::NEGY => [x, y.wrapping_sub(1), z],
+                        Direction::POSZ => [x, y, z + 1],
+                        Direction::NEGZ => [x, y, z.wrapping_sub(1)],
+                    };
+                    let other = map.get(neighbor).map(|other_id| &node_set.node_dict()[other_id]);
+                    let other = other.unwrap_or_else(|| panic!("part at {:?} cut off by the edge of the map", [x, y, z]));
+
+                    assert_eq!(other.asset_name, node.asset_name);
+                    assert_eq!((other.reflection, other.orientation), (node.reflection, node.orientation));
+                    assert_eq!(other.part.map(|part| part.offset), authored);
+                }
+            }
+        }
+        assert!(parts > 0);
+    }
+
+    /// Three colors that can't be placed next to the same color along `X` or `Z`, which often runs into contradictions.
+    fn colors() -> NodeSet {
+        let node_set = NodeSetBuilder::new(1)
+            .asset("red", Array3::from_elem((1, 1, 1), 1))
+            .asset("green", Array3::from_elem((1, 1, 1), 2))
+            .asset("blue", Array3::from_elem((1, 1, 1), 3))
+            .build()
+            .unwrap();
+
+        let mut rules = node_set.rules();
+        for rule in &mut rules.nodes {
+            let others = (0..3).filter(|id| *id != rule.id).collect::<Vec<usize>>();
+            rule.neighbors.px = others.clone();
+            rule.neighbors.nx = others.clone();
+            rule.neighbors.pz = others.clone();
+            rule.neighbors.nz = others;
+        }
+        NodeSet::from_rules(&rules).unwrap()
+    }
+
+    #[test]
+    fn retries_keep_earlier_constraints() {
+        let node_set = colors();
+        let red = node_set.asset_bits(&"red".to_string()).unwrap().clone();
+
+        let mut retried = 0;
+        for seed in 0..40 {
+            let mut solver = Solver::from_seed([8, 1, 8], node_set.bit_mask(), &node_set, &seed);
+            solver.set_heuristic(&Heuristic::Random);
+            solver.constrain_list(&[3, 0, 3], &!red.clone());
+
+            let solution = solver.solve_with_retries(&20).unwrap();
+            assert!(red[solution.map[[3, 0, 3]]]);
+            if solution.attempt > 0 { retried += 1; }
+        }
+        assert!(retried > 0);
+    }
+
+    #[test]
+    fn steps_collapse_one_cell_at_a_time_until_finished() {
+        let node_set = node_set();
+        let mut solver = Solver::from_seed([6, 4, 6], node_set.bit_mask(), &node_set, &1);
+
+        let mut collapses = 0;
+        loop {
+            match solver.step() {
+                Step::Collapsed { pos, node_id } => {
+                    collapses += 1;
+                    assert_eq!(solver.options_at(&pos).iter_ones().collect::<Vec<usize>>(), [node_id]);
+                },
+                Step::Finished => break,
+                step => panic!("unexpected {:?}", step),
+            }
+            assert!(collapses <= 6 * 4 * 6);
+        }
+
+        assert!(collapses > 0);
+        assert!(solver.collapsed());
+        assert_eq!(solver.step(), Step::Finished);
+    }
+
+    #[test]
+    fn restored_snapshots_solve_the_same_map() {
+        let node_set = node_set();
+        let air = node_set.asset_bits(&"air".to_string()).unwrap().clone();
+
+        for propagator in [Propagator::Union, Propagator::SupportCount] {
+            let mut solver = Solver::from_seed([6, 4, 6], node_set.bit_mask(), &node_set, &5);
+            solver.set_propagator(&propagator);
+            let snapshot = solver.snapshot();
+            let map = solver.solve();
+            assert!(map.is_some());
+
+            solver.restore(&snapshot);
+            solver.constrain_list(&[0, 0, 0], &air);
+            solver.restore(&snapshot);
+            assert_eq!(solver.solve(), map);
+        }
+    }
+
+    #[test]
+    fn undo_restores_the_map_before_each_decision() {
+        let node_set = node_set();
+
+        for propagator in [Propagator::Union, Propagator::SupportCount] {
+            let mut solver = Solver::from_seed([6, 4, 6], node_set.bit_mask(), &node_set, &3);
+            solver.set_propagator(&propagator);
+            solver.set_max_backtrack_depth(&usize::MAX);
+
+            let mut states = Vec::new();
+            while solver.wave.uncollapsed() > 0 && solver.wave.empty() == 0 {
+                states.push((state(&solver), solver.wave.uncollapsed()));
+                if let Step::Backtracked { .. } = solver.step() {
+                    states.truncate(solver.decisions.len());
+                }
+            }
+            assert!(solver.collapsed());
+
+            while let Some(decision) = solver.decisions.pop_back() {
+                solver.undo(decision.trail);
+                let (state_before, uncollapsed_before) = states.pop().unwrap();
+                assert!(state(&solver) == state_before);
+                assert_eq!(solver.wave.uncollapsed(), uncollapsed_before);
+            }
+        }
+    }
+}